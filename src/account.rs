@@ -0,0 +1,208 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeSet, HashMap},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{field::Field, Accounts};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(from = "StoredAccount")]
+pub struct Account {
+    pub fields: HashMap<String, Field>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub tags: BTreeSet<String>,
+    /// slash separated path such as work/aws/prod
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub folder: Option<String>,
+    /// unix time the account was created, unknown for accounts from older
+    /// vaults
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<i64>,
+    /// unix time of the last change to the account
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<i64>,
+}
+
+/// Accounts are equal when their contents are, whenever they were written
+impl PartialEq for Account {
+    fn eq(&self, other: &Self) -> bool {
+        self.fields == other.fields && self.tags == other.tags && self.folder == other.folder
+    }
+}
+
+impl Eq for Account {}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum SortKey {
+    #[default]
+    Name,
+    Modified,
+    Created,
+}
+
+/// accounts were stored as a bare map of fields before tags and folders
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredAccount {
+    Full {
+        fields: HashMap<String, Field>,
+        #[serde(default)]
+        tags: BTreeSet<String>,
+        #[serde(default)]
+        folder: Option<String>,
+        #[serde(default)]
+        created: Option<i64>,
+        #[serde(default)]
+        modified: Option<i64>,
+    },
+    Bare(HashMap<String, Field>),
+}
+
+impl From<StoredAccount> for Account {
+    fn from(stored: StoredAccount) -> Self {
+        match stored {
+            StoredAccount::Full {
+                fields,
+                tags,
+                folder,
+                created,
+                modified,
+            } => Self {
+                fields,
+                tags,
+                folder,
+                created,
+                modified,
+            },
+            StoredAccount::Bare(fields) => Self::new(fields),
+        }
+    }
+}
+
+impl Account {
+    pub fn new(fields: HashMap<String, Field>) -> Self {
+        Self {
+            fields,
+            ..Default::default()
+        }
+    }
+
+    /// whether the account has every tag and sits in the folder or below it
+    pub fn matches(&self, tags: &[String], folder: Option<&str>) -> bool {
+        let in_folder = match (folder.and_then(normalize_folder), &self.folder) {
+            (None, _) => true,
+            (Some(f), Some(own)) => own == &f || own.starts_with(&format!("{}/", f)),
+            (Some(_), None) => false,
+        };
+        in_folder && tags.iter().all(|t| self.tags.contains(t))
+    }
+}
+
+/// Stamps accounts added or changed since `before` with the current time
+pub fn stamp(before: &Accounts, after: &mut Accounts) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+    for (name, account) in after.iter_mut() {
+        match before.get(name) {
+            Some(old) if old == account => {}
+            // renamed accounts keep their creation time
+            Some(_) => account.modified = Some(now),
+            None => {
+                account.created.get_or_insert(now);
+                account.modified = Some(now);
+            }
+        }
+    }
+}
+
+/// sorts by name, or most recent first by time with ties sorted by name
+pub fn sorted<'a>(
+    accounts: impl Iterator<Item = (&'a String, &'a Account)>,
+    key: SortKey,
+) -> Vec<(&'a String, &'a Account)> {
+    let mut accounts: Vec<_> = accounts.collect();
+    accounts.sort_by(|(a_name, a), (b_name, b)| {
+        let by_time = match key {
+            SortKey::Name => Ordering::Equal,
+            SortKey::Modified => b.modified.cmp(&a.modified),
+            SortKey::Created => b.created.cmp(&a.created),
+        };
+        by_time.then_with(|| a_name.cmp(b_name))
+    });
+    accounts
+}
+
+/// trims and collapses slashes, None for the top level
+pub fn normalize_folder(path: &str) -> Option<String> {
+    let path = path
+        .split('/')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("/");
+    (!path.is_empty()).then_some(path)
+}
+
+#[cfg(test)]
+mod account_tests {
+    use super::*;
+
+    #[test]
+    fn test_bare_account() {
+        let account: Account =
+            serde_json::from_str(r#"{"pass": {"kind": "password", "value": "x"}}"#).unwrap();
+        assert_eq!(account.fields["pass"].value, "x");
+        assert!(account.tags.is_empty() && account.folder.is_none());
+    }
+
+    #[test]
+    fn test_matches() {
+        let account = Account {
+            tags: BTreeSet::from([String::from("work"), String::from("cloud")]),
+            folder: normalize_folder("/work//aws/prod/"),
+            ..Default::default()
+        };
+        assert_eq!(account.folder.as_deref(), Some("work/aws/prod"));
+        assert!(account.matches(&[], None));
+        assert!(account.matches(&[String::from("work")], Some("work/aws")));
+        assert!(account.matches(&[], Some("work/aws/prod/")));
+        assert!(!account.matches(&[], Some("work/aw")));
+        assert!(!account.matches(&[String::from("home")], None));
+    }
+
+    #[test]
+    fn test_stamp_and_sort() {
+        let before = HashMap::from([
+            (String::from("old"), Account::default()),
+            (
+                String::from("same"),
+                Account {
+                    created: Some(1),
+                    ..Default::default()
+                },
+            ),
+        ]);
+        let mut after = before.clone();
+        after.get_mut("old").unwrap().folder = Some(String::from("moved"));
+        after.insert(String::from("new"), Account::default());
+        stamp(&before, &mut after);
+
+        assert!(after["old"].modified.is_some() && after["old"].created.is_none());
+        assert!(after["new"].modified.is_some() && after["new"].created.is_some());
+        assert!(after["same"].modified.is_none());
+
+        let names = |key| {
+            sorted(after.iter(), key)
+                .into_iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(SortKey::Name), ["new", "old", "same"]);
+        assert_eq!(names(SortKey::Modified), ["new", "old", "same"]);
+        assert_eq!(names(SortKey::Created), ["new", "same", "old"]);
+    }
+}
@@ -0,0 +1,142 @@
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose, Engine};
+use log::info;
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+};
+
+use crate::{
+    config::{AttachArgs, ExtractArgs},
+    confirm,
+    crypt::create_private,
+    field::{Field, FieldKind},
+    App, Error, CONFIRM_OVERWRITE_PROMPT,
+};
+
+/// Stores a file in an account as an attachment field, named after the file
+/// unless a field is given
+pub fn handle_attach(app: &mut App, args: &AttachArgs) -> Result<()> {
+    let (account, file) = (&args.account, &args.file);
+    let name = match &args.field {
+        Some(f) => f.clone(),
+        None => file
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| Error::Usage(String::from("Invalid attachment path")))?
+            .to_string(),
+    };
+
+    // read at most one byte past the limit, the file may grow while it's read
+    let limit = app.config.max_attachment_size;
+    let mut data = Vec::new();
+    File::open(file)?
+        .take(limit.saturating_add(1))
+        .read_to_end(&mut data)?;
+    if data.len() as u64 > limit {
+        return Err(anyhow!("Attachment is over the limit of {} bytes", limit));
+    }
+    let value = general_purpose::STANDARD.encode(data);
+
+    let account_map = app.vault.accounts_mut().entry(account.clone()).or_default();
+    if account_map.fields.contains_key(&name)
+        && !confirm(CONFIRM_OVERWRITE_PROMPT, false, &args.force)?
+    {
+        return Err(Error::Aborted.into());
+    }
+    account_map
+        .fields
+        .insert(name, Field::new(FieldKind::Attachment, value)?);
+    info!("Attachment stored");
+    Ok(())
+}
+
+/// Writes an attachment to the given file, readable by the owner only, or to
+/// stdout
+pub fn handle_extract(app: &App, args: &ExtractArgs) -> Result<()> {
+    let (account, field) = (&args.account, &args.field);
+    let field = app
+        .vault
+        .accounts()
+        .get(account)
+        .ok_or_else(|| Error::AccountNotFound(account.clone()))?
+        .fields
+        .get(field)
+        .filter(|f| f.kind == FieldKind::Attachment)
+        .ok_or_else(|| Error::FieldNotFound {
+            account: account.clone(),
+            field: field.clone(),
+        })?;
+    let data = general_purpose::STANDARD.decode(&field.value)?;
+
+    match &args.file {
+        Some(path) => {
+            if path.exists() && !confirm(CONFIRM_OVERWRITE_PROMPT, false, &args.force)? {
+                return Err(Error::Aborted.into());
+            }
+            create_private(path)?.write_all(&data)?;
+            info!("Attachment written to {}", path.display());
+        }
+        None => io::stdout().write_all(&data)?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod attachment_tests {
+    use std::fs;
+
+    use crate::Vault;
+
+    use super::*;
+
+    fn app() -> App {
+        let mut app = App::test(Vault::create("", ""));
+        app.config.max_attachment_size = 16;
+        app
+    }
+
+    #[test]
+    fn test_attach_extract() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("key.bin");
+        let data = [0, 159, 146, 150, 255, b'\n', 7];
+        fs::write(&file, data).unwrap();
+
+        let mut app = app();
+        let mut attach = AttachArgs {
+            account: String::from("server"),
+            file: file.clone(),
+            ..Default::default()
+        };
+        handle_attach(&mut app, &attach).unwrap();
+        let field = app.vault.get("server", "key.bin").unwrap();
+        assert_eq!(field.kind, FieldKind::Attachment);
+
+        let out = dir.path().join("out.bin");
+        let extract = ExtractArgs {
+            account: String::from("server"),
+            field: String::from("key.bin"),
+            file: Some(out.clone()),
+            ..Default::default()
+        };
+        handle_extract(&app, &extract).unwrap();
+        assert_eq!(fs::read(&out).unwrap(), data);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&out).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // files up to the limit are stored, larger ones aren't
+        fs::write(&file, [1; 16]).unwrap();
+        attach.field = Some(String::from("full"));
+        handle_attach(&mut app, &attach).unwrap();
+        fs::write(&file, [1; 17]).unwrap();
+        attach.field = Some(String::from("over"));
+        let err = handle_attach(&mut app, &attach).unwrap_err();
+        assert!(err.to_string().contains("limit of 16 bytes"), "{}", err);
+        assert!(app.vault.get("server", "over").is_err());
+    }
+}
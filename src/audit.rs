@@ -0,0 +1,385 @@
+//! Append-only audit log of vault operations.
+//!
+//! The log lives next to the vault as `<file>.log`. The first line names the
+//! log, every following line is one sealed entry recording the operation,
+//! account, field, user and time, never a value. Each entry carries the hash
+//! of the line before it, so edits, removals and reordering break the chain.
+//! The key and the hash of the last entry at the time of the latest vault
+//! write are kept inside the vault, which makes truncation up to that point
+//! detectable. Entries recorded after the last write, such as reads, are
+//! chained but not anchored until the vault is written again. Anchors also
+//! name the user and host keeping the log, so that a log deleted outright is
+//! told apart from the logs other machines keep for a synced vault.
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose, Engine};
+use colored::*;
+use log::{info, warn};
+use orion::{aead, hash};
+use rand::prelude::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use time::{macros::format_description, OffsetDateTime};
+
+use crate::{
+    config::{Command, LogArgs},
+    vault::{AuditState, AuditStates},
+    App,
+};
+
+const HEADER: &str = "passcli-audit";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    seq: u64,
+    time: i64,
+    user: String,
+    op: String,
+    account: Option<String>,
+    field: Option<String>,
+    ok: bool,
+    prev: String,
+}
+
+/// path of the audit log kept next to a vault
+pub fn log_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".log");
+    path.with_file_name(name)
+}
+
+/// Appends an entry for the operation the app just dispatched. Returns
+/// whether a new log was started, its key is lost unless the vault is
+/// written afterwards
+pub fn record(app: &mut App, command: &Command, ok: bool) -> Result<bool> {
+    let (account, field) = command.target();
+    let path = log_path(app.vault.path());
+    let mut lines = read_lines(&path)?;
+
+    // a log whose key isn't in this vault can't be continued
+    if let Some(id) = lines.first().map(|h| log_id(h)).transpose()? {
+        if !app.vault.audit().contains_key(id) {
+            let moved = path.with_extension(format!("log.{}", id));
+            fs::rename(&path, &moved)?;
+            warn!(
+                "Audit log isn't known to this vault, moved to {}",
+                moved.display()
+            );
+            lines.clear();
+        }
+    }
+
+    let created = lines.is_empty();
+    if created {
+        if let Some((id, state)) = missing_log(app.vault.audit(), None) {
+            warn!(
+                "Audit log {} with {} entries is missing, it was deleted or moved. A new log \
+                 is started, log --verify fails until {} is restored",
+                id,
+                state.seq,
+                path.display()
+            );
+        }
+        let id: String = (0..8)
+            .map(|_| format!("{:02x}", thread_rng().gen::<u8>()))
+            .collect();
+        let header = header(&id);
+        fs::write(&path, format!("{}\n", header))?;
+        app.vault.audit_mut().insert(
+            id,
+            AuditState {
+                key: aead::SecretKey::default().unprotected_as_bytes().to_vec(),
+                seq: 0,
+                head: digest(&header)?,
+                origin: origin(),
+            },
+        );
+        info!("Audit log created at {}", path.display());
+        lines.push(header);
+    }
+
+    let id = log_id(&lines[0])?;
+    let state = app
+        .vault
+        .audit_mut()
+        .get_mut(id)
+        .ok_or_else(|| anyhow!("Audit log key missing"))?;
+    // logs created before origins were recorded are claimed by the machine
+    // continuing them
+    if state.origin.is_empty() {
+        state.origin = origin();
+    }
+    let entry = Entry {
+        seq: lines.len() as u64,
+        time: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64,
+        user: user(),
+        op: format!("{:?}", command.op()).to_lowercase(),
+        account: account.cloned(),
+        field: field.cloned(),
+        ok,
+        prev: digest(lines.last().unwrap())?,
+    };
+
+    let key = aead::SecretKey::from_slice(&state.key)?;
+    let line = general_purpose::STANDARD.encode(aead::seal(&key, &serde_json::to_vec(&entry)?)?);
+    let mut file = OpenOptions::new().append(true).open(&path)?;
+    writeln!(file, "{}", line)?;
+
+    // the anchor only persists if the vault is written after this
+    state.seq = entry.seq;
+    state.head = digest(&line)?;
+    Ok(created)
+}
+
+/// Prints the audit log, or with --verify checks it against the vault
+pub fn handle_log(app: &App, args: &LogArgs) -> Result<()> {
+    let path = log_path(app.vault.path());
+    let lines = read_lines(&path)?;
+    if lines.is_empty() {
+        if let Some((id, state)) = missing_log(app.vault.audit(), None) {
+            return Err(missing_error(id, state));
+        }
+        info!("No audit log found");
+        return Ok(());
+    }
+
+    let (entries, unanchored) = verify(app.vault.audit(), &lines)?;
+    // a log deleted and replaced by a new one
+    if let Some((id, state)) = missing_log(app.vault.audit(), Some(log_id(&lines[0])?)) {
+        return Err(missing_error(id, state));
+    }
+    if args.verify {
+        info!(
+            "Audit log verified: {} entries, {} recorded since the last vault write",
+            entries.len(),
+            unanchored
+        );
+        return Ok(());
+    }
+
+    let format = format_description!("[year]-[month]-[day] [hour]:[minute]:[second]");
+    for entry in entries {
+        let time = OffsetDateTime::from_unix_timestamp(entry.time)?.format(&format)?;
+        let target = [entry.account, entry.field]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(" ");
+        println!(
+            "{} {} {} {}{}",
+            time.dimmed(),
+            entry.user.blue(),
+            entry.op.magenta(),
+            target.green(),
+            if entry.ok { "" } else { " (failed)" }.red()
+        );
+    }
+    Ok(())
+}
+
+/// Decrypts every entry and checks the chain and the vault's anchor,
+/// returning the entries and how many follow the anchor
+fn verify(states: &AuditStates, lines: &[String]) -> Result<(Vec<Entry>, u64)> {
+    let id = log_id(&lines[0])?;
+    let state = states
+        .get(id)
+        .ok_or_else(|| anyhow!("Audit log {} isn't known to this vault", id))?;
+    let key = aead::SecretKey::from_slice(&state.key)?;
+
+    let mut entries = Vec::new();
+    for (seq, pair) in (1..).zip(lines.windows(2)) {
+        let sealed = general_purpose::STANDARD
+            .decode(&pair[1])
+            .map_err(|_| anyhow!("Audit log entry {} is malformed", seq))?;
+        let entry: Entry = aead::open(&key, &sealed)
+            .ok()
+            .and_then(|plain| serde_json::from_slice(&plain).ok())
+            .ok_or_else(|| anyhow!("Audit log entry {} failed authentication", seq))?;
+        if entry.seq != seq {
+            return Err(anyhow!("Audit log entry {} is out of sequence", seq));
+        }
+        if entry.prev != digest(&pair[0])? {
+            return Err(anyhow!(
+                "Audit log entry {} doesn't follow the entry before it",
+                seq
+            ));
+        }
+        entries.push(entry);
+    }
+
+    let count = entries.len() as u64;
+    if count < state.seq {
+        return Err(anyhow!(
+            "Audit log truncated: {} entries, the vault expects at least {}",
+            count,
+            state.seq
+        ));
+    }
+    if digest(&lines[state.seq as usize])? != state.head {
+        return Err(anyhow!(
+            "Audit log entry {} doesn't match the vault",
+            state.seq
+        ));
+    }
+    Ok((entries, count - state.seq))
+}
+
+/// an anchored log kept by this user and host other than the one next to
+/// the vault, which means it was deleted
+fn missing_log<'a>(
+    states: &'a AuditStates,
+    present: Option<&str>,
+) -> Option<(&'a String, &'a AuditState)> {
+    let origin = origin();
+    states
+        .iter()
+        .filter(|(id, _)| Some(id.as_str()) != present)
+        .find(|(_, state)| state.seq > 0 && state.origin == origin)
+}
+
+fn missing_error(id: &str, state: &AuditState) -> anyhow::Error {
+    anyhow!(
+        "Audit log {} is missing, the vault expects at least {} entries",
+        id,
+        state.seq
+    )
+}
+
+fn user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_default()
+}
+
+/// user and host keeping the logs written here
+fn origin() -> String {
+    format!(
+        "{}@{}",
+        user(),
+        whoami::fallible::hostname().unwrap_or_default()
+    )
+}
+
+fn header(id: &str) -> String {
+    format!("{} {}", HEADER, id)
+}
+
+fn read_lines(path: &Path) -> Result<Vec<String>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(String::from)
+        .collect())
+}
+
+fn log_id(header: &str) -> Result<&str> {
+    header
+        .strip_prefix(HEADER)
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .ok_or_else(|| anyhow!("Audit log header is malformed"))
+}
+
+/// hex encoded BLAKE2b-256 of a log line
+fn digest(line: &str) -> Result<String> {
+    Ok(hash::digest(line.as_bytes())?
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+#[cfg(test)]
+mod audit_tests {
+    use crate::{config::AddArgs, Vault};
+
+    use super::*;
+
+    fn add() -> Command {
+        Command::Add(AddArgs {
+            account: String::from("mail"),
+            ..Default::default()
+        })
+    }
+
+    fn app(dir: &Path) -> App {
+        App::test(Vault::create(dir.join("passwd"), "audit test password"))
+    }
+
+    #[test]
+    fn test_log_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut app = app(dir.path());
+        assert!(record(&mut app, &add(), true).unwrap());
+        for _ in 0..2 {
+            assert!(!record(&mut app, &add(), true).unwrap());
+        }
+
+        let path = log_path(app.vault.path());
+        let lines = read_lines(&path).unwrap();
+        let (entries, unanchored) = verify(app.vault.audit(), &lines).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(unanchored, 0);
+        assert_eq!(entries[2].account.as_deref(), Some("mail"));
+
+        // dropping the last entry is caught by the anchor
+        assert!(verify(app.vault.audit(), &lines[..3]).is_err());
+
+        // removing an entry from the middle breaks the chain
+        let removed = [&lines[..2], &lines[3..]].concat();
+        assert!(verify(app.vault.audit(), &removed).is_err());
+
+        // altering an entry fails authentication
+        let mut edited = lines.clone();
+        let flipped = if edited[2].starts_with('A') { "B" } else { "A" };
+        edited[2].replace_range(..1, flipped);
+        assert!(verify(app.vault.audit(), &edited).is_err());
+
+        // entries after an older anchor still verify
+        let mut older = app.vault.audit().clone();
+        let state = older.values_mut().next().unwrap();
+        state.seq = 2;
+        state.head = digest(&lines[2]).unwrap();
+        assert_eq!(verify(&older, &lines).unwrap().1, 1);
+    }
+
+    #[test]
+    fn test_deleted_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut app = app(dir.path());
+        let verify = LogArgs { verify: true };
+        // a log another machine keeps for the synced vault isn't expected here
+        let mut foreign = app.vault.audit().clone();
+        foreign.insert(
+            String::from("0123456789abcdef"),
+            AuditState {
+                key: vec![0; 32],
+                seq: 4,
+                head: String::new(),
+                origin: String::from("someone@elsewhere"),
+            },
+        );
+        *app.vault.audit_mut() = foreign;
+        assert!(handle_log(&app, &verify).is_ok());
+
+        record(&mut app, &add(), true).unwrap();
+        record(&mut app, &add(), true).unwrap();
+        assert!(handle_log(&app, &verify).is_ok());
+
+        let path = log_path(app.vault.path());
+        fs::remove_file(&path).unwrap();
+        let err = handle_log(&app, &verify).unwrap_err();
+        assert!(err.to_string().contains("is missing"), "{}", err);
+
+        // a new log doesn't hide the deleted one
+        record(&mut app, &add(), true).unwrap();
+        assert_eq!(app.vault.audit().len(), 3);
+        assert!(handle_log(&app, &verify).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use anyhow::{anyhow, Context, Result};
+use colored::*;
+use log::info;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::{CheckArgs, PassConfig},
+    confirm,
+    crypt::{backup_path, diagnose, Stage},
+    Compression, Error, KdfParams,
+};
+
+/// Checks each layer of the vault and of its backups, printing the stage
+/// each fails at. With repair the newest backup that passes replaces a vault
+/// that doesn't, the damaged file being kept next to it. Returns the exit
+/// code of the vault's error, 0 if it passed or was repaired
+pub fn handle_check(
+    path: &Path,
+    password: &str,
+    args: &CheckArgs,
+    config: &PassConfig,
+) -> Result<i32> {
+    let kdf = config.kdf();
+    let result = check_file(path, password, &kdf);
+
+    let mut good_backup = None;
+    let mut backup_failed_auth = false;
+    for generation in (1..).take_while(|g| backup_path(path, *g).exists()) {
+        match check_file(&backup_path(path, generation), password, &kdf) {
+            Ok(()) => good_backup = good_backup.or(Some(generation)),
+            Err(Error::IncorrectPassword) => backup_failed_auth = true,
+            Err(_) => {}
+        }
+    }
+
+    let err = match result {
+        Ok(()) => return Ok(0),
+        Err(err) => err,
+    };
+    if let Error::IncorrectPassword = err {
+        if good_backup.is_some() {
+            println!("The backups open with this password, the vault was altered or damaged");
+        } else if backup_failed_auth {
+            println!("No backup opens either, the password is likely incorrect");
+        }
+    }
+
+    let generation = match good_backup {
+        Some(generation) => generation,
+        None if args.repair => {
+            return Err(err).context("No backup passes the check, nothing to restore from")
+        }
+        None => return Ok(err.exit_code()),
+    };
+    if !args.repair {
+        println!(
+            "Backup {} passes, run check --repair to restore it",
+            generation
+        );
+        return Ok(err.exit_code());
+    }
+
+    let backup = backup_path(path, generation);
+    let prompt = format!("Restore {} over the vault?", backup.display());
+    if !confirm(&prompt, false, &args.force)? {
+        return Err(Error::Aborted.into());
+    }
+    if path.exists() {
+        let damaged = damaged_path(path);
+        fs::rename(path, &damaged)?;
+        info!("Damaged vault kept at {}", damaged.display());
+    }
+    fs::copy(&backup, path)
+        .map_err(|e| anyhow!("Failed to restore {}: {}", backup.display(), e))?;
+    info!("Vault restored from backup {}", generation);
+    Ok(0)
+}
+
+/// prints each stage of reading a vault file up to the one that fails
+fn check_file(path: &Path, password: &str, kdf: &KdfParams) -> Result<(), Error> {
+    println!("{}", path.display().to_string().bold());
+    let data = fs::read(path).inspect_err(|e| print_stage("read", Some(&e.to_string())))?;
+    print_stage("read", None);
+
+    let result = diagnose(password, &data, kdf);
+    for stage in Stage::ALL {
+        match &result {
+            Err((failed, e)) if *failed == stage => {
+                print_stage(&stage_name(stage), Some(&e.to_string()));
+                break;
+            }
+            _ => print_stage(&stage_name(stage), None),
+        }
+    }
+    let payload = result.map_err(|(_, e)| e)?;
+    let header = payload.header;
+    let compression = match header.compression {
+        Compression::None => String::from("uncompressed"),
+        compression => format!("{} compressed", compression),
+    };
+    println!(
+        "  {} accounts, {} {} with {} iterations, {} KiB, {} lanes, {}",
+        payload.accounts.len(),
+        header.encoding,
+        header.suite,
+        header.kdf.iterations,
+        header.kdf.memory,
+        header.kdf.parallelism,
+        compression
+    );
+    Ok(())
+}
+
+/// prints a stage as passed or with the error it failed with
+fn print_stage(name: &str, error: Option<&str>) {
+    match error {
+        None => println!("  {:<16}{}", name, "ok".green()),
+        Some(e) => println!("  {:<16}{} {}", name, "failed".red(), e),
+    }
+}
+
+fn stage_name(stage: Stage) -> String {
+    format!("{:?}", stage).to_lowercase()
+}
+
+/// path the damaged vault is moved to when a backup is restored over it
+fn damaged_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".damaged");
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod check_tests {
+    use passcli::{
+        error::{EXIT_CORRUPT, EXIT_PASSWORD},
+        Vault,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_repair() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("passwd");
+        let mut vault = Vault::create(&path, "pw");
+        vault.save().unwrap();
+        vault
+            .accounts_mut()
+            .insert(String::from("mail"), Default::default());
+        vault.save().unwrap();
+
+        let config = PassConfig::default();
+        let mut args = CheckArgs::default();
+        assert_eq!(handle_check(&path, "pw", &args, &config).unwrap(), 0);
+        let wrong = handle_check(&path, "wrong", &args, &config).unwrap();
+        assert_eq!(wrong, EXIT_PASSWORD);
+
+        // without repair the backup is only pointed at
+        let good = fs::read(&path).unwrap();
+        let damaged = &good[..good.len() / 2];
+        fs::write(&path, damaged).unwrap();
+        let corrupt = handle_check(&path, "pw", &args, &config).unwrap();
+        assert_eq!(corrupt, EXIT_CORRUPT);
+        assert_eq!(fs::read(&path).unwrap(), damaged);
+
+        args.repair = true;
+        args.force = true;
+        assert_eq!(handle_check(&path, "pw", &args, &config).unwrap(), 0);
+        assert_eq!(fs::read(damaged_path(&path)).unwrap(), damaged);
+        let backup = fs::read(backup_path(&path, 1)).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), backup);
+        assert_eq!(handle_check(&path, "pw", &args, &config).unwrap(), 0);
+
+        // nothing to restore from once every backup is gone
+        fs::write(&path, damaged).unwrap();
+        fs::remove_file(backup_path(&path, 1)).unwrap();
+        assert!(handle_check(&path, "pw", &args, &config).is_err());
+    }
+}
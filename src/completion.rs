@@ -0,0 +1,262 @@
+//! Shell completion scripts that complete operations, account names and
+//! field names.
+//!
+//! The scripts ask `passcli __complete` for names, which can't unlock the
+//! vault. Instead, when `completion_cache` is enabled, every run that opens
+//! the vault seals its account and field names, never values, into a cache
+//! in the user's cache directory. The cache key lives in the runtime
+//! directory, which is memory backed and cleared on logout, so the cache
+//! can't be read once the session ends and is rewritten under a new key the
+//! next time the vault is opened.
+use anyhow::Result;
+use clap::CommandFactory;
+use clap_complete::Shell;
+use log::warn;
+use orion::aead;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use crate::{
+    config::{Cli, CompleteArgs, CompletionsArgs},
+    crypt::create_private,
+    Accounts, App, Error,
+};
+
+/// account names with the names of their fields
+type Names = BTreeMap<String, BTreeSet<String>>;
+
+/// where the cache key and the sealed cache are kept, None without a runtime
+/// directory to keep the key in
+fn cache_paths() -> Option<(PathBuf, PathBuf)> {
+    let key = dirs::runtime_dir()?.join("passcli/names.key");
+    let cache = dirs::cache_dir()?.join("passcli/names");
+    Some((key, cache))
+}
+
+/// Seals the names of the vault's accounts and fields into the completion
+/// cache, or removes the cache if it has been turned off
+pub fn update_cache(app: &App) {
+    if let Err(e) = write_cache(app.vault.accounts(), app.config.completion_cache) {
+        warn!("Failed to update the completion cache: {}", e);
+    }
+}
+
+fn write_cache(accounts: &Accounts, enabled: bool) -> Result<()> {
+    let Some((key_path, cache_path)) = cache_paths() else {
+        return Ok(());
+    };
+    if !enabled {
+        if cache_path.exists() {
+            fs::remove_file(&cache_path)?;
+        }
+        return Ok(());
+    }
+
+    let key = match fs::read(&key_path) {
+        Ok(bytes) => aead::SecretKey::from_slice(&bytes)?,
+        Err(_) => {
+            let key = aead::SecretKey::default();
+            create_private(&key_path)?.write_all(key.unprotected_as_bytes())?;
+            key
+        }
+    };
+    let names: Names = accounts
+        .iter()
+        .map(|(name, account)| (name.clone(), account.fields.keys().cloned().collect()))
+        .collect();
+
+    create_private(&cache_path)?.write_all(&aead::seal(&key, &serde_json::to_vec(&names)?)?)?;
+    Ok(())
+}
+
+/// the cached names, None if there's no cache or its key is gone
+fn read_cache() -> Option<Names> {
+    let (key_path, cache_path) = cache_paths()?;
+    let key = aead::SecretKey::from_slice(&fs::read(key_path).ok()?).ok()?;
+    let plaintext = aead::open(&key, &fs::read(cache_path).ok()?).ok()?;
+    serde_json::from_slice(&plaintext).ok()
+}
+
+/// Prints the names a completion script asks for, one per line: accounts, or
+/// the fields of the account given after it. Prints nothing if the names
+/// aren't cached
+pub fn handle_complete(args: &CompleteArgs) -> Result<()> {
+    let names: Vec<String> = match args.names.as_str() {
+        "accounts" => read_cache().map_or_else(Vec::new, |n| n.into_keys().collect()),
+        "fields" => read_cache()
+            .zip(args.account.as_ref())
+            .and_then(|(mut names, account)| names.remove(account))
+            .map_or_else(Vec::new, |fields| fields.into_iter().collect()),
+        _ => Vec::new(),
+    };
+    let mut stdout = io::stdout().lock();
+    for name in names {
+        writeln!(stdout, "{}", name)?;
+    }
+    Ok(())
+}
+
+/// Prints the completion script for a shell, clap's completion of flags with
+/// the completion of names added on top
+pub fn handle_completions(args: &CompletionsArgs) -> Result<()> {
+    let shell = match args.shell.as_str() {
+        "bash" => Shell::Bash,
+        "zsh" => Shell::Zsh,
+        "fish" => Shell::Fish,
+        _ => {
+            return Err(Error::Usage(String::from(
+                "Completions are available for bash, zsh and fish",
+            ))
+            .into())
+        }
+    };
+    print!("{}", script(shell)?);
+    Ok(())
+}
+
+/// clap's script for a shell followed by the functions completing names
+fn script(shell: Shell) -> Result<String> {
+    let mut command = Cli::command();
+    command.build();
+    let subcommands = || command.get_subcommands().filter(|c| !c.is_hide_set());
+    // flags whose next word is their value rather than a positional
+    let value_flags = command
+        .get_arguments()
+        .chain(subcommands().flat_map(|c| c.get_arguments()))
+        .filter(|a| !a.is_positional())
+        .filter(|a| a.get_num_args().is_some_and(|n| n.min_values() > 0))
+        .flat_map(|a| {
+            let short = a.get_short().map(|s| format!("-{}", s));
+            let long = a.get_long().map(|l| format!("--{}", l));
+            short.into_iter().chain(long)
+        })
+        .collect::<BTreeSet<_>>();
+    // subcommands and their aliases taking an account, or an account and field
+    let taking = |id: &str| {
+        subcommands()
+            .filter(|c| c.get_arguments().any(|a| a.get_id() == id))
+            .flat_map(|c| [c.get_name()].into_iter().chain(c.get_all_aliases()))
+            .collect::<Vec<_>>()
+    };
+    let (account_ops, field_ops) = (taking("account"), taking("field"));
+    let join = |words: Vec<&str>| words.join(" ");
+
+    let mut out = Vec::new();
+    clap_complete::generate(shell, &mut command.clone(), "passcli", &mut out);
+    let mut script = String::from_utf8(out)?;
+    let names = match shell {
+        Shell::Bash => BASH,
+        Shell::Zsh => {
+            // clap completes positionals with _default, as ':account -- help:_default'
+            script = script
+                .lines()
+                .map(|line| {
+                    let function = if line.starts_with("':account -- ")
+                        || line.starts_with("'::account -- ")
+                    {
+                        "_passcli_accounts"
+                    } else if line.starts_with("':field -- ") || line.starts_with("'::field -- ") {
+                        "_passcli_fields"
+                    } else {
+                        return format!("{}\n", line);
+                    };
+                    format!(
+                        "{}\n",
+                        line.replace(":_default'", &format!(":{}'", function))
+                    )
+                })
+                .collect();
+            ZSH
+        }
+        _ => FISH,
+    };
+    let names = names
+        .replace(
+            "VALUE_FLAGS",
+            &join(value_flags.iter().map(String::as_str).collect()),
+        )
+        .replace("ACCOUNT_OPS", &join(account_ops))
+        .replace("FIELD_OPS", &join(field_ops));
+    Ok(format!("{}\n{}", script, names))
+}
+
+const BASH: &str = r#"_passcli_names() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" word skip="" positionals=()
+    for word in "${COMP_WORDS[@]:1:COMP_CWORD-1}"; do
+        if [[ -n ${skip} ]]; then
+            skip=""
+        elif [[ " VALUE_FLAGS " == *" ${word} "* ]]; then
+            skip=1
+        elif [[ ${word} != -* ]]; then
+            positionals+=("${word}")
+        fi
+    done
+
+    local names
+    if [[ -n ${skip} || ${cur} == -* ]]; then
+        _passcli "$@"
+        return
+    elif [[ ${#positionals[@]} -eq 1 && " ACCOUNT_OPS " == *" ${positionals[0]} "* ]]; then
+        names="$(passcli __complete accounts 2>/dev/null)"
+    elif [[ ${#positionals[@]} -eq 2 && " FIELD_OPS " == *" ${positionals[0]} "* ]]; then
+        names="$(passcli __complete fields "${positionals[1]}" 2>/dev/null)"
+    else
+        _passcli "$@"
+        return
+    fi
+    mapfile -t COMPREPLY < <(IFS=$'\n' compgen -W "${names}" -- "${cur}")
+}
+
+complete -F _passcli_names -o bashdefault -o default passcli
+"#;
+
+const ZSH: &str = r#"_passcli_accounts() {
+    compadd -- ${(f)"$(passcli __complete accounts 2>/dev/null)"}
+}
+
+_passcli_fields() {
+    compadd -- ${(f)"$(passcli __complete fields "${line[1]}" 2>/dev/null)"}
+}
+"#;
+
+const FISH: &str = r#"function __passcli_positionals
+    set -l skip 0
+    for word in (commandline -opc)[2..-1]
+        if test $skip = 1
+            set skip 0
+        else if contains -- $word VALUE_FLAGS
+            set skip 1
+        else if not string match -q -- '-*' $word
+            echo $word
+        end
+    end
+end
+
+complete -c passcli -f -n '__fish_seen_subcommand_from ACCOUNT_OPS; and test (count (__passcli_positionals)) -eq 1' -a '(passcli __complete accounts 2>/dev/null)'
+complete -c passcli -f -n '__fish_seen_subcommand_from FIELD_OPS; and test (count (__passcli_positionals)) -eq 2' -a '(passcli __complete fields (__passcli_positionals)[2] 2>/dev/null)'
+"#;
+
+#[cfg(test)]
+mod completion_tests {
+    use super::*;
+
+    #[test]
+    fn test_script() {
+        let bash = script(Shell::Bash).unwrap();
+        assert!(bash.contains("complete -F _passcli_names"));
+        assert!(bash.contains(" add a rm r remove edit e show p print note n "));
+        let flags = bash.lines().find(|l| l.contains("elif [[ \" ")).unwrap();
+        assert!(flags.contains(" --template ") && flags.contains(" -t "));
+        assert!(!flags.contains(" --force "));
+
+        let zsh = script(Shell::Zsh).unwrap();
+        assert!(zsh.contains("':account -- Account to add to:_passcli_accounts'"));
+        assert!(zsh
+            .contains("'::field -- Field to remove instead of the whole account:_passcli_fields'"));
+        assert!(!zsh.contains("'::account -- Account to print, every account if omitted:_default'"));
+    }
+}
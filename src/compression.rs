@@ -0,0 +1,125 @@
+//! Compression of the serialized payload before it's padded and sealed.
+//!
+//! Compressed data is framed with its length so that padding can follow it,
+//! and it's padded after compressing: padding first would compress away and
+//! the ciphertext would then reveal the compressed size exactly. Padded after,
+//! the file reveals the size bucket of the compressed payload, which depends
+//! on how repetitive the accounts are as well as on how large they are.
+use miniz_oxide::{deflate::compress_to_vec, inflate::decompress_to_vec_with_limit};
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+use crate::{Error, Result};
+
+/// deflate level, zlib's default balance of size and speed
+const DEFLATE_LEVEL: u8 = 6;
+/// length prefix of compressed data
+const FRAME_SIZE: usize = 4;
+/// largest payload decompressed, far above any vault
+const MAX_DECOMPRESSED: usize = 1 << 30;
+
+/// How the payload of a vault file is compressed before padding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum Compression {
+    /// what every vault was written with before compression was recorded
+    #[default]
+    None,
+    Deflate,
+}
+
+impl Compression {
+    pub const ALL: [Compression; 2] = [Compression::None, Compression::Deflate];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Deflate => "deflate",
+        }
+    }
+
+    /// Compresses a serialized payload, prefixed with its length
+    pub fn compress(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        let compressed = match self {
+            Self::None => return Ok(data),
+            Self::Deflate => compress_to_vec(&data, DEFLATE_LEVEL),
+        };
+        let len = u32::try_from(compressed.len()).map_err(|_| Error::TooLarge(compressed.len()))?;
+        let mut framed = Vec::with_capacity(FRAME_SIZE + compressed.len());
+        framed.extend_from_slice(&len.to_le_bytes());
+        framed.extend_from_slice(&compressed);
+        Ok(framed)
+    }
+
+    /// Decompresses a payload written by [`Compression::compress`], ignoring
+    /// the padding after it
+    pub fn decompress(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        if *self == Self::None {
+            return Ok(data);
+        }
+        let truncated = || Error::Corrupt(String::from("compressed payload truncated"));
+        let len = data.get(..FRAME_SIZE).ok_or_else(truncated)?;
+        let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
+        let compressed = data
+            .get(FRAME_SIZE..FRAME_SIZE + len)
+            .ok_or_else(truncated)?;
+        decompress_to_vec_with_limit(compressed, MAX_DECOMPRESSED)
+            .map_err(|e| Error::Corrupt(format!("decompression failed: {}", e)))
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|compression| compression.name() == s)
+            .ok_or_else(|| Error::UnsupportedCompression(s.to_string()))
+    }
+}
+
+impl From<Compression> for String {
+    fn from(compression: Compression) -> Self {
+        compression.name().to_string()
+    }
+}
+
+impl TryFrom<String> for Compression {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+#[cfg(test)]
+mod compression_tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let data = br#"{"version": 3, "accounts": {"mail": {}, "bank": {}}}"#.repeat(20);
+        for compression in Compression::ALL {
+            let mut padded = compression.compress(data.clone()).unwrap();
+            padded.resize(padded.len() + 100, b' ');
+            let decompressed = compression.decompress(padded).unwrap();
+            assert_eq!(decompressed.trim_ascii_end(), data);
+        }
+
+        let framed = Compression::Deflate.compress(data).unwrap();
+        assert!(Compression::Deflate
+            .decompress(framed[..framed.len() - 1].to_vec())
+            .is_err());
+        assert!(matches!(
+            "zip".parse::<Compression>(),
+            Err(Error::UnsupportedCompression(_))
+        ));
+    }
+}
@@ -4,12 +4,21 @@ use smart_default::SmartDefault;
 use std::{
     fs::{self, File},
     io::Write,
+    num::NonZeroUsize,
     path::PathBuf,
-    str::FromStr,
 };
 
 use anyhow::Result;
-use clap::Parser;
+use clap::{
+    builder::{PossibleValuesParser, TypedValueParser},
+    Parser, Subcommand,
+};
+
+use crate::{
+    account::{normalize_folder, Account, SortKey},
+    field::FieldKind,
+    Compression, KdfParams, Suite,
+};
 
 #[derive(Debug, SmartDefault, Clone)]
 pub enum Ops {
@@ -19,59 +28,576 @@ pub enum Ops {
     #[default]
     Print,
     Interactive,
+    Sync,
+    Merge,
+    Diff,
+    Log,
+    Check,
+    Note,
+    Attach,
+    Extract,
+    List,
+    Run,
+    Inject,
+    GitCredential,
+    SshAgent,
+    KdfBench,
+    Rekey,
+    Gen,
+    Completions,
+    // names for completion scripts
+    Complete,
+}
+
+impl Ops {
+    /// whether the operation changes the vault and needs it written afterwards
+    pub fn is_mutating(&self) -> bool {
+        matches!(
+            self,
+            Self::Add
+                | Self::Remove
+                | Self::Edit
+                | Self::Sync
+                | Self::Merge
+                | Self::Note
+                | Self::Attach
+                | Self::Rekey
+        )
+    }
+}
+
+/// exit codes listed in the help, kept in sync with [`passcli::error`]
+const EXIT_CODES: &str = "\
+Exit codes:
+  0  success
+  1  any other failure
+  2  invalid arguments
+  3  incorrect master password
+  4  vault corrupt or truncated
+  5  vault version, cipher suite or compression unknown
+  6  account or field not found
+  7  reading or writing a file failed
+  8  cancelled at a prompt
+The run operation exits with the status of its command instead.";
+
+/// Command line password manager keeping accounts in an encrypted vault
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None, after_help = EXIT_CODES)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Vault to use instead of the configured one
+    #[arg(long, global = true)]
+    pub path: Option<PathBuf>,
+    /// Master password, prompted for if omitted
+    #[arg(long, global = true)]
+    pub pass: Option<String>,
+    /// Don't log anything
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+}
+
+/// Operations, printing every account if none is given
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Add an account, or a field to an existing one
+    #[command(visible_alias = "a")]
+    Add(AddArgs),
+    /// Remove an account or one of its fields, or the whole vault
+    #[command(visible_aliases = ["r", "remove"])]
+    Rm(RmArgs),
+    /// Rename or change an account or field, or the master password if no
+    /// account is given
+    #[command(visible_alias = "e")]
+    Edit(EditArgs),
+    /// Print a field of an account, or accounts with every field
+    #[command(visible_aliases = ["p", "print"])]
+    Show(ShowArgs),
+    /// List accounts by folder
+    #[command(visible_aliases = ["ls"])]
+    List(ListArgs),
+    /// Run commands one after another with the vault unlocked once
+    #[command(visible_alias = "i")]
+    Interactive,
+    /// Pull, merge and push the vault's git remote
+    #[command(visible_alias = "s")]
+    Sync(SyncArgs),
+    /// Merge another copy of the vault into this one
+    #[command(visible_alias = "m")]
+    Merge(MergeArgs),
+    /// List what adopting another vault or a backup would change
+    #[command(visible_alias = "d")]
+    Diff(DiffArgs),
+    /// Print the audit log
+    #[command(visible_alias = "l")]
+    Log(LogArgs),
+    /// Check each layer of the vault and its backups, reporting where reading fails
+    Check(CheckArgs),
+    /// Edit a multi-line field such as a note or SSH key in $EDITOR
+    #[command(visible_alias = "n")]
+    Note(NoteArgs),
+    /// Store a file in an account
+    Attach(AttachArgs),
+    /// Write an attachment to a file or stdout
+    #[command(visible_alias = "x")]
+    Extract(ExtractArgs),
+    /// Run a command with secrets in its environment
+    Run(RunArgs),
+    /// Render {{ passcli://account/field }} references in a template
+    Inject(InjectArgs),
+    /// Answer git's credential helper requests
+    GitCredential(GitCredentialArgs),
+    /// Serve SSH keys from the vault to ssh over the agent protocol
+    SshAgent(SshAgentArgs),
+    /// Time key derivation on this machine and recommend parameters
+    KdfBench(KdfBenchArgs),
+    /// Encrypt the vault again with another cipher suite or key derivation
+    /// parameters, the ones not given are kept
+    Rekey(RekeyArgs),
+    /// Generate passwords without opening the vault
+    #[command(visible_alias = "g")]
+    Gen(GenArgs),
+    /// Print a completion script for a shell
+    Completions(CompletionsArgs),
+    /// Print names for completion scripts
+    #[command(name = "__complete", hide = true)]
+    Complete(CompleteArgs),
 }
 
-impl FromStr for Ops {
-    type Err = String;
+impl Default for Command {
+    fn default() -> Self {
+        Self::Show(ShowArgs::default())
+    }
+}
+
+impl Command {
+    /// the operation as recorded in the audit log and commit messages
+    pub fn op(&self) -> Ops {
+        match self {
+            Self::Add(_) => Ops::Add,
+            Self::Rm(_) => Ops::Remove,
+            Self::Edit(_) => Ops::Edit,
+            Self::Show(_) => Ops::Print,
+            Self::List(_) => Ops::List,
+            Self::Interactive => Ops::Interactive,
+            Self::Sync(_) => Ops::Sync,
+            Self::Merge(_) => Ops::Merge,
+            Self::Diff(_) => Ops::Diff,
+            Self::Log(_) => Ops::Log,
+            Self::Check(_) => Ops::Check,
+            Self::Note(_) => Ops::Note,
+            Self::Attach(_) => Ops::Attach,
+            Self::Extract(_) => Ops::Extract,
+            Self::Run(_) => Ops::Run,
+            Self::Inject(_) => Ops::Inject,
+            Self::GitCredential(_) => Ops::GitCredential,
+            Self::SshAgent(_) => Ops::SshAgent,
+            Self::KdfBench(_) => Ops::KdfBench,
+            Self::Rekey(_) => Ops::Rekey,
+            Self::Gen(_) => Ops::Gen,
+            Self::Completions(_) => Ops::Completions,
+            Self::Complete(_) => Ops::Complete,
+        }
+    }
+
+    /// the operation if it changes the vault, git-credential only does when
+    /// storing or erasing
+    pub fn mutating_op(&self) -> Option<Ops> {
+        match self {
+            Self::GitCredential(args) => matches!(args.action.as_str(), "store" | "erase"),
+            command => command.op().is_mutating(),
+        }
+        .then(|| self.op())
+    }
 
-    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
-            "a" | "add" => Ok(Self::Add),
-            "r" | "remove" => Ok(Self::Remove),
-            "p" | "print" => Ok(Self::Print),
-            "e" | "edit" => Ok(Self::Edit),
-            "i" | "interactive" => Ok(Self::Interactive),
-            _ => Err(format!("{} is not a valid operation", s)),
+    /// the account and field the operation acts on, if it acts on one
+    pub fn target(&self) -> (Option<&String>, Option<&String>) {
+        match self {
+            Self::Add(args) => (Some(&args.account), args.field.as_ref()),
+            Self::Rm(args) => (args.account.as_ref(), args.field.as_ref()),
+            Self::Edit(args) => (args.account.as_ref(), args.field.as_ref()),
+            Self::Show(args) => (args.account.as_ref(), args.field.as_ref()),
+            Self::Note(args) => (Some(&args.account), args.field.as_ref()),
+            Self::Attach(args) => (Some(&args.account), args.field.as_ref()),
+            Self::Extract(args) => (Some(&args.account), Some(&args.field)),
+            _ => (None, None),
         }
     }
 }
 
-#[derive(Parser, Debug, SmartDefault)]
-#[command(version, about, long_about = None)]
-pub struct Args {
-    // add, remove, list, copy
-    #[arg(index = 1, value_enum)]
-    pub operation: Option<Ops>,
-    #[arg(index = 2)]
-    pub account: Option<String>,
-    #[arg(index = 3)]
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct AddArgs {
+    /// Account to add to
+    pub account: String,
+    /// Field to add, the configured default field if omitted
     pub field: Option<String>,
+    #[command(flatten)]
+    pub value: ValueArgs,
+    #[command(flatten)]
+    pub meta: MetaArgs,
+    /// Template in the config directory's templates folder to add the account from
+    #[arg(long, conflicts_with_all = ["field", "value", "gen"])]
+    pub template: Option<String>,
+    /// Overwrite without asking
     #[arg(short, long)]
-    pub value: Option<Option<String>>,
-    // disallowed characters for password generator
+    pub force: bool,
+}
+
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct RmArgs {
+    /// Account to remove, every account if omitted
+    pub account: Option<String>,
+    /// Field to remove instead of the whole account
+    pub field: Option<String>,
+    /// Remove without asking
     #[arg(short, long)]
-    pub disallow: Option<String>,
-    // password length for password generator, Some() indicates to use generated password for add
+    pub force: bool,
+}
+
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct EditArgs {
+    /// Account to edit
+    pub account: Option<String>,
+    /// Field to edit
+    pub field: Option<String>,
+    #[command(flatten)]
+    pub value: ValueArgs,
+    /// Replace the field's value, prompting if no value is given
+    #[arg(long)]
+    pub new_password: Option<Option<String>>,
+    /// Edit the whole account as a document in $EDITOR
+    #[arg(long, conflicts_with = "field")]
+    pub editor: bool,
+    #[command(flatten)]
+    pub meta: MetaArgs,
+    /// Tag to take off the account, repeatable
+    #[arg(long)]
+    pub untag: Vec<String>,
+    /// Show values when listing the changes made in $EDITOR
+    #[arg(long, requires = "editor")]
+    pub show: bool,
+    /// Edit without asking
     #[arg(short, long)]
-    pub gen: Option<Option<usize>>,
-    // indicates that list operation should hide actual passwords
+    pub force: bool,
+}
+
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct ShowArgs {
+    /// Account to print, every account if omitted
+    pub account: Option<String>,
+    /// Field to print, the configured default field if omitted
+    pub field: Option<String>,
+    /// Print every field of the account
+    #[arg(short, long, requires = "account")]
+    pub all_fields: bool,
+    /// Mask secret values
     #[arg(long)]
     pub hide: bool,
-    // optional path to use instead of config.default_path
+    #[command(flatten)]
+    pub filter: FilterArgs,
+    #[command(flatten)]
+    pub listing: ListingArgs,
+}
+
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct ListArgs {
+    #[command(flatten)]
+    pub filter: FilterArgs,
+    #[command(flatten)]
+    pub listing: ListingArgs,
+}
+
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct SyncArgs {
+    /// Resolve conflicts in favour of this copy without asking
+    #[arg(short, long)]
+    pub force: bool,
+}
+
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct MergeArgs {
+    /// Vault to merge in
+    pub vault: PathBuf,
+    /// Common ancestor of the vaults being merged
     #[arg(long)]
-    pub path: Option<PathBuf>,
+    pub base: Option<PathBuf>,
+    /// Resolve conflicts in favour of this copy without asking
+    #[arg(short, long)]
+    pub force: bool,
+}
+
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct DiffArgs {
+    /// Vault to compare with
+    #[arg(conflicts_with = "backup")]
+    pub vault: Option<PathBuf>,
+    /// Backup generation to compare with, or twice to compare two backups
+    #[arg(long, num_args = 1..=2)]
+    pub backup: Vec<NonZeroUsize>,
+    /// Show values instead of masking them
     #[arg(long)]
-    pub pass: Option<String>,
+    pub show: bool,
+    /// Print the changes as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct LogArgs {
+    /// Check the log against the vault instead of printing it
+    #[arg(long)]
+    pub verify: bool,
+}
+
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct CheckArgs {
+    /// Restore the newest backup that passes over a vault that doesn't
+    #[arg(long)]
+    pub repair: bool,
+    /// Restore without asking
+    #[arg(short, long, requires = "repair")]
+    pub force: bool,
+}
+
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct NoteArgs {
+    /// Account of the field
+    pub account: String,
+    /// Field to edit, note if omitted
+    pub field: Option<String>,
+    /// Kind of the field, note if omitted for a new field
+    #[arg(short, long, value_enum)]
+    pub kind: Option<FieldKind>,
+}
+
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct AttachArgs {
+    /// Account to attach to
+    pub account: String,
+    /// Field to store the file in, named after the file if omitted
+    pub field: Option<String>,
+    /// File to attach
+    #[arg(long, required = true)]
+    pub file: PathBuf,
+    /// Overwrite without asking
     #[arg(short, long)]
-    pub all_fields: bool,
+    pub force: bool,
+}
+
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct ExtractArgs {
+    /// Account of the attachment
+    pub account: String,
+    /// Field holding the attachment
+    pub field: String,
+    /// File to write, readable by the owner only, stdout if omitted
+    #[arg(long)]
+    pub file: Option<PathBuf>,
+    /// Overwrite without asking
     #[arg(short, long)]
     pub force: bool,
+}
+
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct RunArgs {
+    /// Variable to set, as NAME=account/field, repeatable
     #[arg(long)]
-    pub new_password: Option<Option<String>>,
+    pub env: Vec<String>,
+    /// .env style file of NAME=account/field lines
+    #[arg(long)]
+    pub env_file: Option<PathBuf>,
+    /// Command to run, given after --
+    #[arg(last = true, required = true)]
+    pub command: Vec<String>,
+}
+
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct InjectArgs {
+    /// Template to read, stdin if omitted
     #[arg(short, long)]
+    pub input: Option<PathBuf>,
+    /// File to write, readable by the owner only, stdout if omitted
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct GitCredentialArgs {
+    /// Request git makes of the helper
+    #[arg(value_parser = ["get", "store", "erase"])]
+    pub action: String,
+}
+
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct SshAgentArgs {
+    /// Socket to listen on, a private temporary one if omitted
+    #[arg(long)]
+    pub socket: Option<PathBuf>,
+    /// Ask before each use of a key
+    #[arg(long)]
+    pub confirm: bool,
+    /// Serve the keys of accounts with this tag instead of the configured
+    /// one, repeatable
+    #[arg(short, long)]
+    pub tag: Vec<String>,
+    /// Only serve the keys of accounts in this folder or below it
+    #[arg(long)]
+    pub folder: Option<String>,
+}
+
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct KdfBenchArgs {
+    /// Unlock time to aim for in milliseconds
+    #[arg(long, default_value_t = 1000)]
+    pub target: u64,
+    /// Memory in KiB to start from, the configured amount if omitted
+    #[arg(long)]
+    pub memory: Option<u32>,
+    /// Lanes to split the memory into, the configured number if omitted
+    #[arg(long)]
+    pub parallelism: Option<u32>,
+}
+
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct RekeyArgs {
+    /// Cipher suite to encrypt with
+    #[arg(long, value_parser = PossibleValuesParser::new(Suite::ALL.map(|s| s.name()))
+        .map(|s| s.parse::<Suite>().unwrap()))]
+    pub suite: Option<Suite>,
+    /// Passes over the memory, 3 to 1024
+    #[arg(long)]
+    pub iterations: Option<u32>,
+    /// Memory in KiB, at most 1 GiB
+    #[arg(long)]
+    pub memory: Option<u32>,
+    /// Lanes to split the memory into, at most 64
+    #[arg(long)]
+    pub parallelism: Option<u32>,
+}
+
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct GenArgs {
+    /// Length of the passwords, the configured default if omitted
+    pub len: Option<usize>,
+    /// Characters the passwords can't contain, the configured ones if omitted
+    #[arg(short, long)]
+    pub disallow: Option<String>,
+    /// Number of passwords to print
+    #[arg(short = 'n', long, default_value_t = 1)]
+    pub count: usize,
+    /// Print the entropy of the passwords to stderr
+    #[arg(long)]
+    pub entropy: bool,
+}
+
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct CompletionsArgs {
+    /// Shell to complete in
+    #[arg(value_parser = ["bash", "zsh", "fish"])]
+    pub shell: String,
+}
+
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct CompleteArgs {
+    pub names: String,
+    pub account: Option<String>,
+}
+
+/// the value of an added or edited field
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct ValueArgs {
+    /// Value to set, prompted for if omitted
+    #[arg(short, long)]
+    pub value: Option<Option<String>>,
+    /// Generate the value, optionally of the given length
+    #[arg(short, long)]
+    pub gen: Option<Option<usize>>,
+    /// Characters the generated password can't contain
+    #[arg(short, long, requires = "gen")]
+    pub disallow: Option<String>,
+    /// Kind of the field, guessed from the field name if omitted
+    #[arg(short, long, value_enum)]
+    pub kind: Option<FieldKind>,
+    /// Don't print generated or previous values
+    #[arg(long)]
+    pub hide: bool,
+}
+
+/// tags and folder given to an account
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct MetaArgs {
+    /// Tag to give the account, repeatable
+    #[arg(short, long)]
+    pub tag: Vec<String>,
+    /// Folder to move the account to, empty for the top level
+    #[arg(long)]
+    pub folder: Option<String>,
+}
+
+impl MetaArgs {
+    /// whether any tag or folder argument was given
+    pub fn given(&self) -> bool {
+        !self.tag.is_empty() || self.folder.is_some()
+    }
+
+    /// Applies the tag and folder arguments to an account, returning whether
+    /// any were given. An empty folder moves the account back to the top level
+    pub fn apply(&self, account: &mut Account) -> bool {
+        account.tags.extend(self.tag.iter().cloned());
+        if let Some(folder) = &self.folder {
+            account.folder = normalize_folder(folder);
+        }
+        self.given()
+    }
+}
+
+/// which accounts are listed
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct FilterArgs {
+    /// Only list accounts with this tag, repeatable
+    #[arg(short, long)]
+    pub tag: Vec<String>,
+    /// Only list accounts in this folder or below it
+    #[arg(long)]
+    pub folder: Option<String>,
+}
+
+/// how accounts are listed
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct ListingArgs {
+    /// Order of the accounts, by name if omitted
+    #[arg(long, value_enum)]
+    pub sort: Option<SortKey>,
+    /// List accounts as a table of field counts and modification dates
+    #[arg(long)]
+    pub table: bool,
+    /// Page the listing through $PAGER
+    #[arg(long)]
+    pub pager: bool,
+}
+
+/// The parsed arguments, the global flags and the operation with its own,
+/// printing every account if no operation is given
+#[derive(Debug, Default, Clone)]
+pub struct Args {
+    pub command: Command,
+    // optional path to use instead of config.default_path
+    pub path: Option<PathBuf>,
+    pub pass: Option<String>,
     pub quiet: bool,
 }
 
+impl From<Cli> for Args {
+    fn from(cli: Cli) -> Self {
+        Args {
+            command: cli.command.unwrap_or_default(),
+            path: cli.path,
+            pass: cli.pass,
+            quiet: cli.quiet,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Copy)]
 pub enum LevelFilterConf {
     Off,
@@ -94,6 +620,7 @@ impl From<LevelFilterConf> for LevelFilter {
 }
 
 #[derive(SmartDefault, Debug, Deserialize, Serialize)]
+#[serde(default)]
 pub struct PassConfig {
     #[default(None)]
     pub default_pass: Option<String>,
@@ -111,14 +638,60 @@ pub struct PassConfig {
     pub default_hide: bool,
     #[default(false)]
     pub default_force: bool,
+    // cipher suite new vaults are written with, existing ones keep theirs
+    // until rekeyed
+    #[default(Suite::default())]
+    pub suite: Suite,
+    // Argon2 passes, memory in KiB and lanes new vaults are written with, see
+    // kdf-bench. Existing vaults record theirs, files from before that are
+    // read with these
     #[default(3)]
     pub kdf_iterations: u32,
+    #[default(1 << 16)]
+    pub kdf_memory: u32,
+    #[default(1)]
+    pub kdf_parallelism: u32,
+    // compress the vault before padding and encrypting it, none or deflate
+    #[default(Compression::None)]
+    pub compression: Compression,
+    // write the vault as ASCII armored text that survives copy and paste
+    // instead of binary
+    #[default(false)]
+    pub armor: bool,
+    // number of previous vault generations kept next to it as <file>.1, <file>.2...
+    #[default(3)]
+    pub backup_count: usize,
+    // record every operation in an encrypted log next to the vault
+    #[default(true)]
+    pub audit_log: bool,
+    // largest file in bytes that can be attached to an account
+    #[default(1 << 20)]
+    pub max_attachment_size: u64,
+    // commit the vault to its git repository after every mutating operation
+    #[default(false)]
+    pub git_sync: bool,
+    // remote name or url used by the sync operation
+    #[default(String::from("origin"))]
+    pub sync_remote: String,
+    // remote branch used by the sync operation, defaults to the current branch
+    #[default(None)]
+    pub sync_branch: Option<String>,
+    // keep an encrypted cache of account and field names for shell completion
+    #[default(false)]
+    pub completion_cache: bool,
+    // tag marking the accounts whose SSH keys the ssh-agent operation serves
+    #[default(String::from("ssh"))]
+    pub ssh_agent_tag: String,
+    // command notes and edit --editor open, $VISUAL or $EDITOR when unset
+    #[default(None)]
+    pub editor: Option<String>,
 }
 
 impl PassConfig {
-    pub fn new() -> Result<Self> {
-        if let Some(path) = dirs::config_dir() {
-            let path = path.join("passcli/passcli.toml");
+    /// Reads the config file at path, writing the defaults there if it
+    /// doesn't exist yet
+    pub fn load(path: Option<PathBuf>) -> Result<Self> {
+        if let Some(path) = path {
             if path.exists() {
                 Ok(toml::de::from_str(&fs::read_to_string(&path)?)?)
             } else {
@@ -137,4 +710,64 @@ impl PassConfig {
             Ok(Self::default())
         }
     }
+
+    /// The config file in the user's config directory
+    pub fn path() -> Option<PathBuf> {
+        Some(dirs::config_dir()?.join("passcli/passcli.toml"))
+    }
+
+    /// the parameters new vaults are written with
+    pub fn kdf(&self) -> KdfParams {
+        KdfParams {
+            iterations: self.kdf_iterations,
+            memory: self.kdf_memory,
+            parallelism: self.kdf_parallelism,
+        }
+    }
+}
+
+#[cfg(test)]
+mod config_tests {
+    use super::*;
+
+    fn parse(words: &[&str]) -> Command {
+        let cli = Cli::try_parse_from(["passcli"].iter().chain(words)).unwrap();
+        Args::from(cli).command
+    }
+
+    #[test]
+    fn test_command_target() {
+        let add = parse(&["add", "mail", "pass", "--tag", "work"]);
+        assert_eq!(
+            add.target(),
+            (Some(&String::from("mail")), Some(&String::from("pass")))
+        );
+        assert!(matches!(add.mutating_op(), Some(Ops::Add)));
+
+        // arguments that aren't accounts aren't recorded as one
+        for words in [
+            &["git-credential", "store"][..],
+            &["merge", "other"],
+            &["completions", "bash"],
+        ] {
+            assert_eq!(parse(words).target(), (None, None), "{:?}", words);
+        }
+
+        // git-credential only changes the vault when storing or erasing
+        assert!(parse(&["git-credential", "get"]).mutating_op().is_none());
+        assert!(parse(&["git-credential", "erase"]).mutating_op().is_some());
+        // both are written once by the caller, after the audit log records them
+        assert!(matches!(parse(&["rekey"]).mutating_op(), Some(Ops::Rekey)));
+        assert!(matches!(parse(&["sync"]).mutating_op(), Some(Ops::Sync)));
+        assert!(matches!(parse(&[]), Command::Show(_)));
+    }
+
+    #[test]
+    fn test_backup_generation() {
+        assert!(
+            matches!(parse(&["diff", "--backup", "2", "1"]), Command::Diff(d) if d.backup.len() == 2)
+        );
+        // backup 0 would be the vault itself
+        assert!(Cli::try_parse_from(["passcli", "diff", "--backup", "0"]).is_err());
+    }
 }
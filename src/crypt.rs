@@ -1,103 +1,971 @@
-use anyhow::Result;
+//! Encrypted vault file handling.
+//!
+//! The vault is written as a binary container: the magic bytes `PCLV`, a
+//! container version byte, the length of the [`Header`] as a little endian
+//! `u16`, the header as JSON, the salt and the raw ciphertext. The header
+//! names the [`Suite`], the key derivation parameters and the [`Compression`]
+//! of the payload, if any. Everything before the ciphertext is bound to it as
+//! associated data. The container can also be written ASCII armored, as
+//! base64 lines between `BEGIN` and `END` lines, for pasting into places that
+//! only take text.
+//!
+//! The ciphertext seals the JSON encoded [`Payload`], possibly compressed,
+//! padded with trailing whitespace up to a size bucket before encryption.
+//!
+//! Earlier builds wrote base64 of a JSON envelope holding the header, salt and
+//! ciphertext, or the tuple `(salt, ciphertext)` before headers existed. Both
+//! are still read, the latter with the default suite and the parameters given
+//! by the caller. Vaults written before the payload was versioned hold the
+//! accounts map alone, and version 1 payloads hold untyped fields. Both are
+//! still read and migrated to typed fields. Version 2 accounts are bare maps
+//! of fields, read as accounts without tags or folder.
+//!
+//! Leakage: an observer of the file learns the salt, which is random per
+//! write, the header, and the padded plaintext size, which is
+//! `max(len, MIN_BUCKET)` rounded up to the next power of two. The container
+//! adds a fixed overhead to that for a given header. Account names, field
+//! names, values and the number of accounts are hidden beyond that bucket.
+//! Compression happens before padding, so the bucket is that of the
+//! compressed payload.
 use base64::engine::general_purpose;
 use base64::Engine;
-use orion::{aead, kdf};
-use std::fs::File;
+use orion::kdf;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File};
 use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use crate::{Accounts, App};
+use crate::{
+    compression::Compression,
+    field::{migrate, UntypedAccounts},
+    suite::{KdfParams, Key, Suite},
+    vault::{AuditStates, Vault},
+    Accounts, Error, Result,
+};
 
-const KEY_SIZE: u32 = 32;
 const SALT_SIZE: usize = 16;
+/// smallest padded plaintext size in bytes, every bucket is a power of two
+const MIN_BUCKET: usize = 1 << 10;
+/// payload version written by this build
+const VAULT_VERSION: u32 = 3;
+/// first bytes of a binary vault file
+const MAGIC: &[u8; 4] = b"PCLV";
+/// binary container version written by this build
+const CONTAINER_VERSION: u8 = 1;
+const ARMOR_BEGIN: &str = "-----BEGIN PASSCLI VAULT-----";
+const ARMOR_END: &str = "-----END PASSCLI VAULT-----";
+/// base64 characters per armored line
+const ARMOR_WIDTH: usize = 64;
+
+/// How a vault file was encoded and encrypted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Header {
+    pub suite: Suite,
+    pub kdf: KdfParams,
+    pub compression: Compression,
+    pub encoding: Encoding,
+}
+
+/// How the encrypted vault is laid out in its file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// magic bytes, header and salt followed by the raw ciphertext
+    #[default]
+    Binary,
+    /// the binary container as base64 lines between armor lines
+    Armored,
+    /// base64 of a JSON envelope, written by earlier builds
+    Json,
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Binary => "binary",
+            Self::Armored => "armored",
+            Self::Json => "base64 JSON",
+        })
+    }
+}
+
+/// header as written, the suite and compression are parsed separately so
+/// that unknown ones aren't reported as a malformed file
+#[derive(Serialize, Deserialize)]
+struct StoredHeader {
+    suite: String,
+    kdf: KdfParams,
+    /// absent when the payload isn't compressed, as in earlier files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    compression: Option<String>,
+}
+
+/// envelope of files written by earlier builds
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Envelope {
+    Headed {
+        header: StoredHeader,
+        salt: Vec<u8>,
+        ciphertext: Vec<u8>,
+    },
+    Legacy(Vec<u8>, Vec<u8>),
+}
+
+/// Decrypted contents of a vault file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Payload {
+    pub version: u32,
+    pub accounts: Accounts,
+    #[serde(default)]
+    pub audit: AuditStates,
+    /// how the file was encrypted
+    #[serde(skip)]
+    pub header: Header,
+}
+
+/// borrowed form of Payload used for writing
+#[derive(Serialize)]
+struct VaultRef<'a> {
+    version: u32,
+    accounts: &'a Accounts,
+    audit: &'a AuditStates,
+}
+
+/// the version of a payload, read before the rest so that newer payloads
+/// aren't reported as malformed
+#[derive(Deserialize)]
+struct PayloadVersion {
+    version: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredVault {
+    Versioned(Payload),
+    Untyped {
+        version: u32,
+        accounts: UntypedAccounts,
+        #[serde(default)]
+        audit: AuditStates,
+    },
+    Legacy(UntypedAccounts),
+}
+
+/// Encrypts the vault to its file, keeping the previous file as a backup
+pub fn write_encrypted_file(vault: &Vault) -> Result<()> {
+    let header = StoredHeader {
+        suite: vault.suite.name().to_string(),
+        kdf: vault.kdf,
+        compression: (vault.compression != Compression::None)
+            .then(|| vault.compression.name().to_string()),
+    };
 
-pub fn write_encrypted_file(app: &App) -> Result<i32> {
     // generate salt and derive key
-    let password = kdf::Password::from_slice(app.master_pass.as_bytes())?;
     let salt = kdf::Salt::generate(SALT_SIZE)?;
-    let key = kdf::derive_key(
-        &password,
-        &salt,
-        app.config.kdf_iterations,
-        1 << 16,
-        KEY_SIZE,
+    let key = vault
+        .suite
+        .derive_key(&vault.password, salt.as_ref(), &vault.kdf)?;
+
+    // encrypt passwords, binding the header and salt to them
+    let mut data = container_prefix(&serde_json::to_vec(&header)?, salt.as_ref());
+    let ciphertext = seal_vault(
+        vault.suite,
+        &key,
+        &data,
+        vault.compression,
+        &vault.accounts,
+        &vault.audit,
     )?;
+    data.extend_from_slice(&ciphertext);
+    if vault.armor {
+        data = armor(&data).into_bytes();
+    }
+
+    rotate_backups(&vault.path, vault.backup_count)?;
+    write_atomic(&vault.path, &data)?;
+    Ok(())
+}
+
+/// the container up to the ciphertext, which is its associated data
+fn container_prefix(header: &[u8], salt: &[u8]) -> Vec<u8> {
+    // the header is a suite name and three numbers, far below u16::MAX
+    let header_len = header.len() as u16;
+    let mut data = Vec::with_capacity(MAGIC.len() + 3 + header.len() + salt.len());
+    data.extend_from_slice(MAGIC);
+    data.push(CONTAINER_VERSION);
+    data.extend_from_slice(&header_len.to_le_bytes());
+    data.extend_from_slice(header);
+    data.extend_from_slice(salt);
+    data
+}
+
+/// wraps the binary container in base64 lines between armor lines
+fn armor(data: &[u8]) -> String {
+    let encoded = general_purpose::STANDARD.encode(data);
+    let mut text = format!("{}\n", ARMOR_BEGIN);
+    for line in encoded.as_bytes().chunks(ARMOR_WIDTH) {
+        text.extend(line.iter().map(|&c| c as char));
+        text.push('\n');
+    }
+    text.push_str(ARMOR_END);
+    text.push('\n');
+    text
+}
+
+/// the binary container inside armored text, ignoring whitespace and line
+/// endings changed by copying it around
+fn dearmor(data: &[u8]) -> Result<Vec<u8>> {
+    let text =
+        std::str::from_utf8(data).map_err(|_| Error::Corrupt(String::from("armor isn't text")))?;
+    let body = text
+        .trim()
+        .strip_prefix(ARMOR_BEGIN)
+        .and_then(|body| body.strip_suffix(ARMOR_END))
+        .ok_or_else(|| Error::Corrupt(String::from("armor end line missing")))?;
+    let encoded: String = body.split_whitespace().collect();
+    Ok(general_purpose::STANDARD.decode(encoded)?)
+}
+
+/// path of a backup generation of the vault, 1 being the most recent
+pub fn backup_path(path: &Path, generation: usize) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}", generation));
+    path.with_file_name(name)
+}
+
+/// shifts existing backups up a generation and copies the current vault
+/// into the first, dropping the oldest beyond count
+fn rotate_backups(path: &Path, count: usize) -> Result<()> {
+    if count == 0 || !path.exists() {
+        return Ok(());
+    }
+    for generation in (1..count).rev() {
+        let from = backup_path(path, generation);
+        if from.exists() {
+            fs::rename(&from, backup_path(path, generation + 1))?;
+        }
+    }
+    fs::copy(path, backup_path(path, 1))?;
+    Ok(())
+}
 
-    // encrypt passwords
-    let passwords = serde_json::to_vec(&app.passwords)?;
-    let ciphertext = aead::seal(&key, &passwords)?;
+/// writes to a temporary file next to the target then renames it over the
+/// target, so an interrupted write never leaves a truncated vault
+fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(path.file_name().unwrap_or_default());
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
 
-    // write data to file with salt unencrypted
-    let tuple_data = serde_json::to_vec(&(salt, ciphertext))?;
+    let written = create_private(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&tmp_path, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    Ok(written?)
+}
 
-    let encoded_data = general_purpose::STANDARD.encode(&tuple_data);
+/// Opens a file for writing readable by the owner only, tightening the
+/// permissions of an existing one. Missing parent directories are created
+/// readable by the owner only too
+pub fn create_private(path: &Path) -> std::io::Result<File> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        builder.create(dir)?;
+    }
 
-    let mut file = File::create(app.path.clone())?;
-    file.write_all(encoded_data.as_bytes())?;
-    Ok(0)
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let file = options.open(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(file)
 }
 
-pub fn read_encrypted_file(
-    password: &String,
-    path: &PathBuf,
-    kdf_iterations: &u32,
-) -> Result<Accounts> {
+/// Reads and decrypts a vault file, the parameters are those of files written
+/// before they were recorded in a header
+pub fn read_encrypted_file(password: &str, path: &Path, legacy_kdf: &KdfParams) -> Result<Payload> {
     // read raw file
     let mut file_data = Vec::new();
     File::open(path)?.read_to_end(&mut file_data)?;
-    let decoded_data = general_purpose::STANDARD.decode(file_data)?;
-    let (salt, passwords): (Vec<u8>, Vec<u8>) = serde_json::from_slice(&decoded_data)?;
+    decrypt_vault(password, &file_data, legacy_kdf)
+}
+
+/// decrypts the contents of a vault file, used directly for vaults that
+/// aren't on disk such as blobs from git history
+pub fn decrypt_vault(password: &str, file_data: &[u8], legacy_kdf: &KdfParams) -> Result<Payload> {
+    diagnose(password, file_data, legacy_kdf).map_err(|(_, e)| e)
+}
+
+/// Layers of a vault file in the order they're read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// the armor or base64 text of the file
+    Encoding,
+    /// the container or JSON envelope of header, salt and ciphertext
+    Envelope,
+    /// the suite and key derivation parameters being usable
+    Header,
+    /// the ciphertext opening under the key derived from the password
+    Authentication,
+    /// the plaintext decompressing, when the header names a compression
+    Decompression,
+    /// the payload version being one this build reads
+    Version,
+    /// the decrypted payload parsing as accounts
+    Payload,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 7] = [
+        Stage::Encoding,
+        Stage::Envelope,
+        Stage::Header,
+        Stage::Authentication,
+        Stage::Decompression,
+        Stage::Version,
+        Stage::Payload,
+    ];
+}
+
+/// Decrypts the contents of a vault file like [`decrypt_vault`], telling
+/// which stage failed along with the error
+pub fn diagnose(
+    password: &str,
+    file_data: &[u8],
+    legacy_kdf: &KdfParams,
+) -> Result<Payload, (Stage, Error)> {
+    let unpacked = unpack(file_data)?;
+    let salt = kdf::Salt::from_slice(&unpacked.salt).map_err(|_| {
+        (
+            Stage::Envelope,
+            Error::Corrupt(String::from("invalid salt")),
+        )
+    })?;
+
+    let header = match &unpacked.header {
+        Some(stored) => Header {
+            suite: stored.suite.parse().map_err(|e| (Stage::Header, e))?,
+            kdf: stored.kdf,
+            compression: match &stored.compression {
+                Some(name) => name.parse().map_err(|e| (Stage::Header, e))?,
+                None => Compression::None,
+            },
+            encoding: unpacked.encoding,
+        },
+        None => Header {
+            suite: Suite::default(),
+            kdf: *legacy_kdf,
+            compression: Compression::None,
+            encoding: unpacked.encoding,
+        },
+    };
+    header.kdf.validate().map_err(|e| (Stage::Header, e))?;
 
     // derive key from password and salt
-    let salt = kdf::Salt::from_slice(&salt)?;
-    let password = kdf::Password::from_slice(password.as_bytes())?;
-    let key = kdf::derive_key(&password, &salt, *kdf_iterations, 1 << 16, KEY_SIZE)?;
+    let key = header
+        .suite
+        .derive_key(password, salt.as_ref(), &header.kdf)
+        .map_err(|e| (Stage::Authentication, e))?;
+
+    // decrypt and deserialize passwords, padding is trailing whitespace
+    // which the JSON parser skips, or follows the compressed payload
+    let plaintext = header
+        .suite
+        .open(&key, &unpacked.ciphertext, &unpacked.aad)
+        .map_err(|e| (Stage::Authentication, e))?;
+    let plaintext = header
+        .compression
+        .decompress(plaintext)
+        .map_err(|e| (Stage::Decompression, e))?;
+    // legacy payloads are maps of accounts and have no version to read
+    if let Ok(PayloadVersion { version }) = serde_json::from_slice(&plaintext) {
+        if version > VAULT_VERSION {
+            return Err((
+                Stage::Version,
+                Error::UnsupportedVersion {
+                    found: version,
+                    supported: VAULT_VERSION,
+                },
+            ));
+        }
+    }
+    let payload =
+        match serde_json::from_slice(&plaintext).map_err(|e| (Stage::Payload, e.into()))? {
+            StoredVault::Versioned(vault) => vault,
+            StoredVault::Untyped {
+                version,
+                accounts,
+                audit,
+            } => Payload {
+                version,
+                accounts: migrate(accounts),
+                audit,
+                ..Default::default()
+            },
+            StoredVault::Legacy(accounts) => Payload {
+                version: 0,
+                accounts: migrate(accounts),
+                ..Default::default()
+            },
+        };
+    Ok(Payload { header, ..payload })
+}
+
+/// a vault file split into its parts
+struct Unpacked {
+    encoding: Encoding,
+    /// none for files written before headers
+    header: Option<StoredHeader>,
+    salt: Vec<u8>,
+    ciphertext: Vec<u8>,
+    aad: Vec<u8>,
+}
+
+/// splits a vault file in any of the encodings into its parts
+fn unpack(file_data: &[u8]) -> Result<Unpacked, (Stage, Error)> {
+    if file_data.starts_with(MAGIC) {
+        return unpack_container(file_data, Encoding::Binary);
+    }
+    if file_data
+        .trim_ascii_start()
+        .starts_with(ARMOR_BEGIN.as_bytes())
+    {
+        let data = dearmor(file_data).map_err(|e| (Stage::Encoding, e))?;
+        return unpack_container(&data, Encoding::Armored);
+    }
+
+    let decoded_data = general_purpose::STANDARD
+        .decode(file_data)
+        .map_err(|e| (Stage::Encoding, e.into()))?;
+    let envelope =
+        serde_json::from_slice(&decoded_data).map_err(|e| (Stage::Envelope, e.into()))?;
+    Ok(match envelope {
+        Envelope::Headed {
+            header,
+            salt,
+            ciphertext,
+        } => Unpacked {
+            encoding: Encoding::Json,
+            aad: serde_json::to_vec(&header).map_err(|e| (Stage::Envelope, e.into()))?,
+            header: Some(header),
+            salt,
+            ciphertext,
+        },
+        Envelope::Legacy(salt, ciphertext) => Unpacked {
+            encoding: Encoding::Json,
+            header: None,
+            salt,
+            ciphertext,
+            aad: Vec::new(),
+        },
+    })
+}
+
+/// splits a binary container, see the module docs for its layout
+fn unpack_container(data: &[u8], encoding: Encoding) -> Result<Unpacked, (Stage, Error)> {
+    let corrupt = |reason: &str| (Stage::Envelope, Error::Corrupt(reason.to_string()));
+    let version = *data
+        .get(MAGIC.len())
+        .ok_or_else(|| corrupt("container truncated"))?;
+    if version != CONTAINER_VERSION {
+        return Err((
+            Stage::Envelope,
+            Error::UnsupportedVersion {
+                found: version.into(),
+                supported: CONTAINER_VERSION.into(),
+            },
+        ));
+    }
+    let header_start = MAGIC.len() + 3;
+    let header_len = data
+        .get(MAGIC.len() + 1..header_start)
+        .ok_or_else(|| corrupt("container truncated"))?;
+    let salt_start = header_start + u16::from_le_bytes([header_len[0], header_len[1]]) as usize;
+    let ciphertext_start = salt_start + SALT_SIZE;
+    if data.len() < ciphertext_start {
+        return Err(corrupt("container truncated"));
+    }
+    let header: StoredHeader = serde_json::from_slice(&data[header_start..salt_start])
+        .map_err(|e| (Stage::Envelope, e.into()))?;
+    // containers are always padded, so the plaintext is a whole bucket and a
+    // cut off file isn't mistaken for a wrong password. An unknown suite is
+    // reported by the header stage
+    if let Ok(suite) = header.suite.parse::<Suite>() {
+        let sealed = data.len() - ciphertext_start;
+        let padded = sealed.saturating_sub(suite.overhead());
+        if padded < MIN_BUCKET || !padded.is_power_of_two() {
+            return Err(corrupt("ciphertext truncated"));
+        }
+    }
+    Ok(Unpacked {
+        encoding,
+        header: Some(header),
+        salt: data[salt_start..ciphertext_start].to_vec(),
+        ciphertext: data[ciphertext_start..].to_vec(),
+        aad: data[..ciphertext_start].to_vec(),
+    })
+}
+
+/// serializes, compresses and pads the vault, then seals it with the key
+fn seal_vault(
+    suite: Suite,
+    key: &Key,
+    aad: &[u8],
+    compression: Compression,
+    accounts: &Accounts,
+    audit: &AuditStates,
+) -> Result<Vec<u8>> {
+    let vault = VaultRef {
+        version: VAULT_VERSION,
+        accounts,
+        audit,
+    };
+    let plaintext = pad(compression.compress(serde_json::to_vec(&vault)?)?);
+    suite.seal(key, &plaintext, aad)
+}
 
-    // decrypt and deserialize passwords
-    let plaintext = aead::open(&key, &passwords)?;
-    Ok(serde_json::from_slice(&plaintext)?)
+/// pads plaintext with spaces up to its size bucket
+fn pad(mut plaintext: Vec<u8>) -> Vec<u8> {
+    let bucket = plaintext.len().max(MIN_BUCKET).next_power_of_two();
+    plaintext.resize(bucket, b' ');
+    plaintext
 }
 
 #[cfg(test)]
 mod crypto_tests {
     use std::collections::HashMap;
 
-    use crate::config::{Args, PassConfig};
+    use crate::{
+        account::Account,
+        field::{Field, FieldKind},
+    };
 
     use super::*;
 
+    /// builds a vault of n accounts with a single field of the given length
+    fn accounts(n: usize, value_len: usize) -> Accounts {
+        (0..n)
+            .map(|i| {
+                (
+                    format!("account {}", i),
+                    Account::new(HashMap::from([(
+                        String::from("pass"),
+                        Field {
+                            kind: FieldKind::Password,
+                            value: "x".repeat(value_len),
+                        },
+                    )])),
+                )
+            })
+            .collect()
+    }
+
+    fn seal(key: &Key, passwords: &Accounts) -> Vec<u8> {
+        seal_vault(
+            Suite::default(),
+            key,
+            b"",
+            Compression::None,
+            passwords,
+            &AuditStates::new(),
+        )
+        .unwrap()
+    }
+
+    /// length of the serialized vault before padding
+    fn plain_len(passwords: &Accounts) -> usize {
+        let vault = VaultRef {
+            version: VAULT_VERSION,
+            accounts: passwords,
+            audit: &AuditStates::new(),
+        };
+        serde_json::to_vec(&vault).unwrap().len()
+    }
+
+    /// length of the padded plaintext a ciphertext carries
+    fn sealed_len(passwords: &Accounts) -> usize {
+        let key = Key::generate();
+        let ciphertext = seal(&key, passwords);
+        // nonce and tag are fixed size
+        ciphertext.len() - 24 - 16
+    }
+
     #[test]
     fn test_io() {
-        let mut app = App {
-            args: Args::default(),
-            config: PassConfig::new().unwrap(),
-            path: PathBuf::from("test/passwds"),
-            master_pass: String::from("crypto test password"),
-            passwords: HashMap::from([
-                (
-                    String::from("account 1"),
-                    HashMap::from([
-                        (String::from("pass2"), String::from("thisispass2")),
-                        (String::from("pass"), String::from("thisispass1")),
-                    ]),
-                ),
-                (
-                    String::from("account 2"),
-                    HashMap::from([
-                        (String::from("pass2"), String::from("thisispass2")),
-                        (String::from("pass"), String::from("thisispass1")),
-                    ]),
-                ),
-            ]),
-            interactive: false,
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("passwd");
+        let mut vault = Vault::create(&path, "crypto test password")
+            .suite(Suite::Argon2idAes256Gcm)
+            .backup_count(0);
+        *vault.accounts_mut() = migrate(HashMap::from([
+            (
+                String::from("account 1"),
+                HashMap::from([
+                    (String::from("pass2"), String::from("thisispass2")),
+                    (String::from("pass"), String::from("thisispass1")),
+                ]),
+            ),
+            (
+                String::from("account 2"),
+                HashMap::from([
+                    (String::from("pass2"), String::from("thisispass2")),
+                    (String::from("pass"), String::from("thisispass1")),
+                ]),
+            ),
+        ]));
+
+        for (armor, encoding) in [(false, Encoding::Binary), (true, Encoding::Armored)] {
+            vault.armor = armor;
+            write_encrypted_file(&vault).unwrap();
+            let decrypted =
+                read_encrypted_file(vault.password(), &path, &KdfParams::default()).unwrap();
+
+            assert_eq!(decrypted.version, VAULT_VERSION);
+            assert_eq!(decrypted.header.suite, Suite::Argon2idAes256Gcm);
+            assert_eq!(decrypted.header.encoding, encoding);
+            assert_eq!(vault.accounts(), &decrypted.accounts);
+        }
+    }
+
+    #[test]
+    fn test_write_atomic() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("passwd");
+        let tmp_path = dir.path().join(".passwd.tmp");
+
+        // a stale temporary file doesn't keep its permissions
+        std::fs::write(&tmp_path, b"stale").unwrap();
+        write_atomic(&path, b"first").unwrap();
+        write_atomic(&path, b"second").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+        assert!(!tmp_path.exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // renaming over a directory fails, the temporary file is removed
+        let blocked = dir.path().join("blocked");
+        std::fs::create_dir(&blocked).unwrap();
+        std::fs::write(blocked.join("inside"), b"").unwrap();
+        assert!(write_atomic(&blocked, b"data").is_err());
+        assert!(!dir.path().join(".blocked.tmp").exists());
+
+        // missing directories are created for the owner only
+        let nested = dir.path().join("private/nested/file");
+        create_private(&nested).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(dir.path().join("private"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+        assert!(nested.exists());
+    }
+
+    #[test]
+    fn test_container() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("passwd");
+        let mut vault = Vault::create(&path, "pw").backup_count(0);
+        *vault.accounts_mut() = accounts(3, 10);
+        write_encrypted_file(&vault).unwrap();
+        let data = std::fs::read(&path).unwrap();
+
+        // only the header and salt are added to the sealed padded plaintext
+        let header = serde_json::to_vec(&StoredHeader {
+            suite: Suite::default().name().to_string(),
+            kdf: KdfParams::default(),
+            compression: None,
+        })
+        .unwrap();
+        assert!(data.starts_with(MAGIC));
+        assert_eq!(
+            data.len(),
+            MAGIC.len() + 3 + header.len() + SALT_SIZE + 24 + MIN_BUCKET + 16
+        );
+
+        // armor survives reflowed lines and carriage returns
+        let pasted = armor(&data).replace('\n', "\r\n  ");
+        assert_eq!(dearmor(pasted.as_bytes()).unwrap(), data);
+
+        // the prefix is associated data, even an equivalent header fails to open
+        let salt_start = MAGIC.len() + 3 + header.len();
+        let mut altered = container_prefix(
+            &[&header[..], b" "].concat(),
+            &data[salt_start..salt_start + SALT_SIZE],
+        );
+        altered.extend_from_slice(&data[salt_start + SALT_SIZE..]);
+        let (stage, _) = diagnose("pw", &altered, &KdfParams::default()).unwrap_err();
+        assert_eq!(stage, Stage::Authentication);
+    }
+
+    #[test]
+    fn test_padding_hides_account_count() {
+        let sizes: Vec<usize> = [0, 1, 5, 12]
+            .iter()
+            .map(|&n| sealed_len(&accounts(n, 8)))
+            .collect();
+        assert!(sizes.iter().all(|&s| s == MIN_BUCKET), "{:?}", sizes);
+    }
+
+    #[test]
+    fn test_padding_reveals_only_bucket() {
+        for (n, value_len) in [(1, 2000), (40, 40), (3, 5000), (200, 100)] {
+            let passwords = accounts(n, value_len);
+            let plain_len = plain_len(&passwords);
+            let len = sealed_len(&passwords);
+            assert!(len.is_power_of_two());
+            assert!(len >= plain_len && len < plain_len.max(MIN_BUCKET) * 2);
+        }
+    }
+
+    #[test]
+    fn test_padding_same_bucket_same_size() {
+        // different names, counts and values that land in the same bucket
+        let small = accounts(2, 600);
+        let mut large = accounts(20, 20);
+        large.insert(String::from("bank"), Account::default());
+        assert_eq!(sealed_len(&small), sealed_len(&large));
+    }
+
+    #[test]
+    fn test_padded_plaintext_round_trip() {
+        let passwords = accounts(3, 10);
+        let key = Key::generate();
+        let ciphertext = seal(&key, &passwords);
+        let plaintext = Suite::default().open(&key, &ciphertext, b"").unwrap();
+        assert_eq!(plaintext.len(), MIN_BUCKET);
+        let decoded: Payload = serde_json::from_slice(&plaintext).unwrap();
+        assert_eq!(decoded.accounts, passwords);
+    }
+
+    /// a vault like a large real one, random passwords and notes in prose
+    fn realistic_accounts(n: usize) -> Accounts {
+        use rand::distributions::{Alphanumeric, DistString};
+        let mut rng = rand::thread_rng();
+        (0..n)
+            .map(|i| {
+                let fields = HashMap::from([
+                    (
+                        String::from("user"),
+                        Field {
+                            kind: FieldKind::Username,
+                            value: format!("user{}@example.com", i),
+                        },
+                    ),
+                    (
+                        String::from("pass"),
+                        Field {
+                            kind: FieldKind::Password,
+                            value: Alphanumeric.sample_string(&mut rng, 20),
+                        },
+                    ),
+                    (
+                        String::from("notes"),
+                        Field {
+                            kind: FieldKind::Note,
+                            value: format!(
+                                "Security questions for site {}: first pet was {}, \
+                                 recovery codes are kept in the safe",
+                                i,
+                                Alphanumeric.sample_string(&mut rng, 8)
+                            ),
+                        },
+                    ),
+                ]);
+                (format!("site {}", i), Account::new(fields))
+            })
+            .collect()
+    }
+
+    #[test]
+    #[ignore = "benchmark, run with --ignored --nocapture to see the sizes and times"]
+    fn test_compression_benchmark() {
+        let passwords = realistic_accounts(5000);
+        let key = Key::generate();
+        let mut sizes = Vec::new();
+        for compression in Compression::ALL {
+            let start = std::time::Instant::now();
+            let sealed = seal_vault(
+                Suite::default(),
+                &key,
+                b"",
+                compression,
+                &passwords,
+                &AuditStates::new(),
+            )
+            .unwrap();
+            let sealing = start.elapsed();
+            let start = std::time::Instant::now();
+            let plaintext = Suite::default().open(&key, &sealed, b"").unwrap();
+            let decoded: Payload =
+                serde_json::from_slice(&compression.decompress(plaintext).unwrap()).unwrap();
+            let opening = start.elapsed();
+            assert_eq!(decoded.accounts, passwords);
+            println!(
+                "{} accounts, {}: {} bytes of {} serialized, sealed in {:?}, opened in {:?}",
+                passwords.len(),
+                compression,
+                sealed.len(),
+                plain_len(&passwords),
+                sealing,
+                opening
+            );
+            sizes.push(sealed.len());
+        }
+        assert!(sizes[1] * 2 <= sizes[0], "{:?}", sizes);
+    }
+
+    #[test]
+    fn test_padding_after_compression() {
+        let key = Key::generate();
+        let sealed_len = |passwords: &Accounts| {
+            let audit = AuditStates::new();
+            let deflate = Compression::Deflate;
+            seal_vault(Suite::default(), &key, b"", deflate, passwords, &audit)
+                .unwrap()
+                .len()
+                - 24
+                - 16
         };
+        // the padding isn't compressed away, sizes are still buckets
+        for n in [0, 1, 40, 300, 2000] {
+            let passwords = realistic_accounts(n);
+            let len = sealed_len(&passwords);
+            assert!(len.is_power_of_two() && len >= MIN_BUCKET);
+            assert!(len < plain_len(&passwords).max(MIN_BUCKET) * 2);
+        }
+        // vaults whose compressed sizes share a bucket can't be told apart
+        assert_eq!(
+            sealed_len(&realistic_accounts(2)),
+            sealed_len(&accounts(30, 500))
+        );
+    }
+
+    #[test]
+    fn test_legacy_payload() {
+        let untyped: UntypedAccounts = HashMap::from([(
+            String::from("mail"),
+            HashMap::from([(String::from("user"), String::from("me"))]),
+        )]);
+        let plaintext = pad(serde_json::to_vec(&untyped).unwrap());
+        match serde_json::from_slice(&plaintext).unwrap() {
+            StoredVault::Legacy(decoded) => {
+                assert_eq!(
+                    migrate(decoded)["mail"].fields["user"].kind,
+                    FieldKind::Username
+                )
+            }
+            _ => panic!("legacy payload read as versioned"),
+        }
+    }
+
+    /// a vault file from before headers, sealing the plaintext under the
+    /// password
+    fn file_of(password: &str, plaintext: &[u8]) -> Vec<u8> {
+        let salt = kdf::Salt::generate(SALT_SIZE).unwrap();
+        let suite = Suite::default();
+        let key = suite
+            .derive_key(password, salt.as_ref(), &KdfParams::default())
+            .unwrap();
+        let ciphertext = suite.seal(&key, plaintext, b"").unwrap();
+        let tuple = serde_json::to_vec(&(salt, ciphertext)).unwrap();
+        general_purpose::STANDARD.encode(tuple).into_bytes()
+    }
+
+    #[test]
+    fn test_newer_version() {
+        // fields added by a newer build don't hide its version
+        let newer = file_of("pw", br#"{"version": 9, "accounts": {}, "extra": 1}"#);
+        assert!(matches!(
+            decrypt_vault("pw", &newer, &KdfParams::default()),
+            Err(Error::UnsupportedVersion { found: 9, .. })
+        ));
+    }
 
-        app.args.path = Some(PathBuf::from("test/crypt_test_file"));
+    #[test]
+    fn test_diagnose() {
+        let dir = tempfile::tempdir().unwrap();
+        let kdf = KdfParams::default();
+        let stage = |data: &[u8], password| diagnose(password, data, &kdf).unwrap_err().0;
+        let good = file_of("pw", br#"{"version": 3, "accounts": {}}"#);
+        assert!(diagnose("pw", &good, &kdf).is_ok());
 
-        write_encrypted_file(&app).unwrap();
-        let decrypted_passwords =
-            read_encrypted_file(&app.master_pass, &app.args.path.unwrap(), &3).unwrap();
+        assert_eq!(stage(&good[..good.len() - 3], "pw"), Stage::Encoding);
+        let envelope = general_purpose::STANDARD.encode(b"[[1, 2], [3");
+        assert_eq!(stage(envelope.as_bytes(), "pw"), Stage::Envelope);
+        let unknown = Envelope::Headed {
+            header: StoredHeader {
+                suite: String::from("rot13"),
+                kdf,
+                compression: None,
+            },
+            salt: vec![0; SALT_SIZE],
+            ciphertext: vec![0; 64],
+        };
+        let unknown = general_purpose::STANDARD.encode(serde_json::to_vec(&unknown).unwrap());
+        assert_eq!(stage(unknown.as_bytes(), "pw"), Stage::Header);
+        // parameters read before authentication can't exhaust memory
+        let huge = container_prefix(
+            br#"{"suite":"argon2id-aes256gcm","kdf":{"iterations":3,"memory":4294967295,"parallelism":1}}"#,
+            &[0; SALT_SIZE],
+        );
+        let sealed = vec![0; Suite::Argon2idAes256Gcm.overhead() + MIN_BUCKET];
+        assert_eq!(stage(&[huge, sealed].concat(), "pw"), Stage::Header);
+        let truncated = container_prefix(b"{}", &[0; SALT_SIZE]);
+        assert_eq!(stage(&truncated[..10], "pw"), Stage::Envelope);
+        let vault = Vault::create(dir.path().join("passwd"), "pw").backup_count(0);
+        write_encrypted_file(&vault).unwrap();
+        let binary = std::fs::read(vault.path()).unwrap();
+        assert!(diagnose("pw", &binary, &kdf).is_ok());
+        for cut in [1, 16, MIN_BUCKET] {
+            let (stage, err) = diagnose("pw", &binary[..binary.len() - cut], &kdf).unwrap_err();
+            assert_eq!(stage, Stage::Envelope);
+            assert!(matches!(err, Error::Corrupt(_)));
+        }
+        let armored = armor(&truncated);
+        assert_eq!(stage(&armored.as_bytes()[..40], "pw"), Stage::Encoding);
+        assert_eq!(stage(&good, "wrong"), Stage::Authentication);
+        let newer = file_of("pw", br#"{"version": 9, "accounts": {}, "extra": 1}"#);
+        assert_eq!(stage(&newer, "pw"), Stage::Version);
+        let schema = file_of("pw", br#"{"version": 3, "accounts": []}"#);
+        assert_eq!(stage(&schema, "pw"), Stage::Payload);
+    }
 
-        assert_eq!(app.passwords, decrypted_passwords);
+    #[test]
+    fn test_ciphertext_hides_names() {
+        let passwords = accounts(5, 10);
+        let key = Key::generate();
+        let ciphertext = seal(&key, &passwords);
+        let needle = b"account 1";
+        assert!(!ciphertext.windows(needle.len()).any(|w| w == needle));
     }
 }
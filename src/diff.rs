@@ -0,0 +1,426 @@
+use anyhow::{anyhow, Context, Result};
+use colored::*;
+use serde::Serialize;
+use std::{borrow::Cow, collections::BTreeSet, path::PathBuf};
+
+use crate::{
+    config::DiffArgs,
+    crypt::{backup_path, read_encrypted_file},
+    field::MASK,
+    Account, Accounts, App, Error,
+};
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Change {
+    Added,
+    Removed,
+    Changed,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+struct AccountDiff {
+    account: String,
+    change: Change,
+    fields: Vec<FieldDiff>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<TagDiff>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    folder: Option<FolderDiff>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+struct TagDiff {
+    tag: String,
+    change: Change,
+}
+
+/// a move between folders, None being the top level
+#[derive(Debug, Serialize, PartialEq)]
+struct FolderDiff {
+    old: Option<String>,
+    new: Option<String>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+struct FieldDiff {
+    field: String,
+    change: Change,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    old: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    new: Option<String>,
+    /// either side is of a kind that's masked when hiding
+    #[serde(skip)]
+    secret: bool,
+}
+
+/// reads the old and new side of the diff
+fn sides<'a>(app: &'a App, args: &DiffArgs) -> Result<(Cow<'a, Accounts>, Accounts)> {
+    let read = |path: PathBuf| {
+        if !path.exists() {
+            return Err(anyhow!("Vault {} not found", path.display()));
+        }
+        read_encrypted_file(app.vault.password(), &path, &app.config.kdf())
+            .map(|vault| vault.accounts)
+            .with_context(|| format!("Failed to read vault {}", path.display()))
+    };
+
+    match (&args.vault, args.backup.as_slice()) {
+        (Some(other), []) => Ok((Cow::Borrowed(app.vault.accounts()), read(other.clone())?)),
+        (None, [generation]) => Ok((
+            Cow::Borrowed(app.vault.accounts()),
+            read(backup_path(app.vault.path(), generation.get()))?,
+        )),
+        (None, [old, new]) => Ok((
+            Cow::Owned(read(backup_path(app.vault.path(), old.get()))?),
+            read(backup_path(app.vault.path(), new.get()))?,
+        )),
+        _ => Err(Error::Usage(String::from(
+            "Diff takes either a vault file or one or two backup generations",
+        ))
+        .into()),
+    }
+}
+
+/// Lists what adopting another vault would change in this one. The vault is
+/// compared with another vault file, or with a backup generation, and two
+/// backup generations can be compared with each other
+pub fn handle_diff(app: &App, args: &DiffArgs) -> Result<()> {
+    let (old, new) = sides(app, args)?;
+    let mut diffs = diff_accounts(&old, &new);
+    if !args.show {
+        mask(&mut diffs);
+    }
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&diffs)?);
+    } else {
+        for diff in &diffs {
+            print_diff(diff);
+        }
+    }
+    Ok(())
+}
+
+/// prints the changes between two sets of accounts, masked unless shown
+pub fn print_changes(old: &Accounts, new: &Accounts, show: bool) {
+    let mut diffs = diff_accounts(old, new);
+    if !show {
+        mask(&mut diffs);
+    }
+    for diff in &diffs {
+        print_diff(diff);
+    }
+}
+
+/// compares two vaults, accounts, fields and tags sorted by name. Accounts
+/// whose only difference is when they were created or modified are left out
+fn diff_accounts(old: &Accounts, new: &Accounts) -> Vec<AccountDiff> {
+    let empty = Account::default();
+    let names: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let (o, n) = (old.get(name), new.get(name));
+            let (o_account, n_account) = (o.unwrap_or(&empty), n.unwrap_or(&empty));
+            let fields = diff_fields(o_account, n_account);
+            let tags = diff_tags(o_account, n_account);
+            let folder = (o_account.folder != n_account.folder).then(|| FolderDiff {
+                old: o_account.folder.clone(),
+                new: n_account.folder.clone(),
+            });
+            let change = match (o, n) {
+                (None, Some(_)) => Change::Added,
+                (Some(_), None) => Change::Removed,
+                _ if !fields.is_empty() || !tags.is_empty() || folder.is_some() => Change::Changed,
+                _ => return None,
+            };
+            Some(AccountDiff {
+                account: name.clone(),
+                change,
+                fields,
+                tags,
+                folder,
+            })
+        })
+        .collect()
+}
+
+fn diff_tags(old: &Account, new: &Account) -> Vec<TagDiff> {
+    let removed = old.tags.difference(&new.tags).map(|t| (t, Change::Removed));
+    let added = new.tags.difference(&old.tags).map(|t| (t, Change::Added));
+    let mut tags: Vec<TagDiff> = removed
+        .chain(added)
+        .map(|(tag, change)| TagDiff {
+            tag: tag.clone(),
+            change,
+        })
+        .collect();
+    tags.sort_by(|a, b| a.tag.cmp(&b.tag));
+    tags
+}
+
+fn diff_fields(old: &Account, new: &Account) -> Vec<FieldDiff> {
+    let names: BTreeSet<&String> = old.fields.keys().chain(new.fields.keys()).collect();
+
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let (o, n) = (old.fields.get(name), new.fields.get(name));
+            let change = match (o, n) {
+                (None, Some(_)) => Change::Added,
+                (Some(_), None) => Change::Removed,
+                (Some(o), Some(n)) if o != n => Change::Changed,
+                _ => return None,
+            };
+            Some(FieldDiff {
+                field: name.clone(),
+                change,
+                old: o.map(|f| f.value.clone()),
+                new: n.map(|f| f.value.clone()),
+                secret: o.into_iter().chain(n).any(|f| f.kind.is_secret()),
+            })
+        })
+        .collect()
+}
+
+/// replaces the values of secret fields with a mask, removed values are
+/// dropped entirely
+fn mask(diffs: &mut [AccountDiff]) {
+    let fields = diffs.iter_mut().flat_map(|d| d.fields.iter_mut());
+    for field in fields.filter(|f| f.secret) {
+        field.old = None;
+        field.new = field.new.as_ref().map(|_| String::from(MASK));
+    }
+}
+
+/// the marker a change is listed with
+fn marker(change: &Change) -> ColoredString {
+    match change {
+        Change::Added => "+".green(),
+        Change::Removed => "-".red(),
+        Change::Changed => "~".yellow(),
+    }
+}
+
+fn print_diff(diff: &AccountDiff) {
+    let name = match diff.change {
+        Change::Added => diff.account.green(),
+        Change::Removed => diff.account.red(),
+        Change::Changed => diff.account.magenta(),
+    };
+    println!("{} {}:", marker(&diff.change), name);
+
+    for field in &diff.fields {
+        let value = match (&field.old, &field.new) {
+            (Some(o), Some(n)) => format!(": {} -> {}", o, n),
+            (_, Some(v)) | (Some(v), None) => format!(": {}", v),
+            (None, None) => String::new(),
+        };
+        println!(
+            "    {} {}{}",
+            marker(&field.change),
+            field.field.green(),
+            value
+        );
+    }
+    for tag in &diff.tags {
+        println!("    {} tag {}", marker(&tag.change), tag.tag.dimmed());
+    }
+    if let Some(folder) = &diff.folder {
+        let show = |f: &Option<String>| format!("{}/", f.as_deref().unwrap_or_default()).blue();
+        println!(
+            "    {} folder: {} -> {}",
+            marker(&Change::Changed),
+            show(&folder.old),
+            show(&folder.new)
+        );
+    }
+}
+
+#[cfg(test)]
+mod diff_tests {
+    use std::{collections::HashMap, num::NonZeroUsize};
+
+    use crate::{field::migrate, Vault};
+
+    use super::*;
+
+    #[test]
+    fn test_diff_accounts() {
+        let old = migrate(HashMap::from([
+            (
+                String::from("mail"),
+                HashMap::from([
+                    (String::from("pass"), String::from("a")),
+                    (String::from("user"), String::from("me")),
+                ]),
+            ),
+            (String::from("bank"), HashMap::new()),
+            (String::from("same"), HashMap::new()),
+        ]));
+        let new = migrate(HashMap::from([
+            (
+                String::from("mail"),
+                HashMap::from([
+                    (String::from("pass"), String::from("b")),
+                    (String::from("pin"), String::from("1234")),
+                ]),
+            ),
+            (String::from("same"), HashMap::new()),
+            (String::from("shop"), HashMap::new()),
+        ]));
+
+        let mut diffs = diff_accounts(&old, &new);
+        mask(&mut diffs);
+
+        let field = |name: &str, change, old: Option<&str>, new: Option<&str>| FieldDiff {
+            field: name.into(),
+            change,
+            old: old.map(String::from),
+            new: new.map(String::from),
+            secret: name != "user",
+        };
+        assert_eq!(
+            diffs,
+            vec![
+                AccountDiff {
+                    account: "bank".into(),
+                    change: Change::Removed,
+                    fields: vec![],
+                    tags: vec![],
+                    folder: None,
+                },
+                AccountDiff {
+                    account: "mail".into(),
+                    change: Change::Changed,
+                    fields: vec![
+                        field("pass", Change::Changed, None, Some(MASK)),
+                        field("pin", Change::Added, None, Some(MASK)),
+                        // usernames aren't secret and stay readable
+                        field("user", Change::Removed, Some("me"), None),
+                    ],
+                    tags: vec![],
+                    folder: None,
+                },
+                AccountDiff {
+                    account: "shop".into(),
+                    change: Change::Added,
+                    fields: vec![],
+                    tags: vec![],
+                    folder: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_tags_and_folder() {
+        let mut account = Account {
+            tags: BTreeSet::from([String::from("home"), String::from("mail")]),
+            ..Default::default()
+        };
+        let old = HashMap::from([(String::from("mail"), account.clone())]);
+
+        // only the times differ, nothing to list
+        account.modified = Some(1);
+        let touched = HashMap::from([(String::from("mail"), account.clone())]);
+        assert!(diff_accounts(&old, &touched).is_empty());
+
+        account.tags = BTreeSet::from([String::from("mail"), String::from("work")]);
+        account.folder = Some(String::from("work/mail"));
+        let new = HashMap::from([(String::from("mail"), account)]);
+        let tag = |tag: &str, change| TagDiff {
+            tag: tag.into(),
+            change,
+        };
+        assert_eq!(
+            diff_accounts(&old, &new),
+            vec![AccountDiff {
+                account: "mail".into(),
+                change: Change::Changed,
+                fields: vec![],
+                tags: vec![tag("home", Change::Removed), tag("work", Change::Added)],
+                folder: Some(FolderDiff {
+                    old: None,
+                    new: Some(String::from("work/mail")),
+                }),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_diff_sides() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut app = App::test(Vault::create(dir.path().join("passwd"), "pw"));
+        // each write keeps the previous one as backup 1
+        for name in ["first", "second", "third"] {
+            app.vault
+                .accounts_mut()
+                .insert(String::from(name), Account::default());
+            app.vault.save().unwrap();
+        }
+        let names = |accounts: &Accounts| {
+            let mut names: Vec<String> = accounts.keys().cloned().collect();
+            names.sort();
+            names
+        };
+        let backup = |generations: &[usize]| DiffArgs {
+            backup: generations
+                .iter()
+                .map(|&g| NonZeroUsize::new(g).unwrap())
+                .collect(),
+            ..Default::default()
+        };
+
+        // one backup is compared with the vault, two with each other
+        let (old, new) = sides(&app, &backup(&[1])).unwrap();
+        assert_eq!(names(&old), ["first", "second", "third"]);
+        assert_eq!(names(&new), ["first", "second"]);
+        let (old, new) = sides(&app, &backup(&[2, 1])).unwrap();
+        assert_eq!(names(&old), ["first"]);
+        assert_eq!(names(&new), ["first", "second"]);
+        let err = sides(&app, &backup(&[3])).unwrap_err();
+        assert!(err.to_string().contains("not found"), "{}", err);
+
+        let other = DiffArgs {
+            vault: Some(backup_path(app.vault.path(), 2)),
+            ..Default::default()
+        };
+        let (_, new) = sides(&app, &other).unwrap();
+        assert_eq!(names(&new), ["first"]);
+        assert!(sides(&app, &DiffArgs::default()).is_err());
+    }
+
+    #[test]
+    fn test_diff_json() {
+        let old = migrate(HashMap::from([(
+            String::from("mail"),
+            HashMap::from([(String::from("pass"), String::from("a"))]),
+        )]));
+        let mut new = old.clone();
+        let mail = new.get_mut("mail").unwrap();
+        mail.fields.get_mut("pass").unwrap().value = String::from("b");
+        mail.tags.insert(String::from("work"));
+
+        let mut diffs = diff_accounts(&old, &new);
+        assert_eq!(
+            serde_json::to_value(&diffs).unwrap(),
+            serde_json::json!([{
+                "account": "mail",
+                "change": "changed",
+                "fields": [{"field": "pass", "change": "changed", "old": "a", "new": "b"}],
+                "tags": [{"tag": "work", "change": "added"}],
+            }])
+        );
+
+        // masked output keeps no old value and hides the new one
+        mask(&mut diffs);
+        assert_eq!(
+            serde_json::to_value(&diffs[0].fields).unwrap(),
+            serde_json::json!([{"field": "pass", "change": "changed", "new": MASK}])
+        );
+    }
+}
@@ -0,0 +1,329 @@
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap},
+    env,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+};
+use tempfile::TempDir;
+
+use crate::{
+    account::{normalize_folder, Account},
+    config::{EditArgs, NoteArgs},
+    confirm,
+    diff::print_changes,
+    field::{Field, FieldKind},
+    App, Error, CONFIRM_OVERWRITE_PROMPT,
+};
+
+const DOCUMENT_HEADER: &str = "\
+# Edit the account below and save to apply, leave it unchanged to cancel.
+# Rename the account or a field by changing its name, remove a field by
+# deleting its table and add one with a new [fields.<name>] table.
+# Folders are slash separated paths, tags a list of strings.
+# Kinds: password, text, username, email, url, note, totp, pin, sshkey, date, attachment
+
+";
+
+/// An account as presented for editing
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AccountDocument {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    folder: Option<String>,
+    #[serde(default)]
+    tags: BTreeSet<String>,
+    #[serde(default)]
+    fields: BTreeMap<String, Field>,
+}
+
+/// Opens the user's editor on text in a private directory, preferring memory
+/// backed filesystems so plaintext never reaches the disk. The file is
+/// overwritten before it's removed, along with its directory
+pub fn edit_secure(initial: &str, extension: &str, editor: &str) -> Result<String> {
+    let dir = private_dir()?;
+    let path = dir.path().join(format!("passcli.{}", extension));
+    fs::write(&path, initial)?;
+
+    let result = run_editor(editor, &path).and_then(|_| Ok(fs::read_to_string(&path)?));
+    wipe(&path)?;
+    result
+}
+
+/// Edits a multi-line field such as a note or SSH key in $EDITOR
+pub fn handle_note(app: &mut App, args: &NoteArgs) -> Result<()> {
+    let account = &args.account;
+    let field = args.field.clone().unwrap_or_else(|| String::from("note"));
+    let existing = app
+        .vault
+        .accounts()
+        .get(account)
+        .and_then(|a| a.fields.get(&field));
+    let kind = args
+        .kind
+        .or(existing.map(|f| f.kind))
+        .unwrap_or(FieldKind::Note);
+    let initial = existing.map(|f| f.value.clone()).unwrap_or_default();
+
+    let value = edit_secure(&initial, "txt", &editor_command(app))?;
+    if value == initial || value.trim().is_empty() {
+        info!("Nothing was changed");
+        return Ok(());
+    }
+
+    let field_value = Field::new(kind, value)?;
+    match app
+        .vault
+        .accounts_mut()
+        .entry(account.clone())
+        .or_default()
+        .fields
+        .entry(field)
+    {
+        Entry::Occupied(mut entry) => {
+            entry.insert(field_value);
+            info!("Field edited");
+        }
+        Entry::Vacant(entry) => {
+            entry.insert(field_value);
+            info!("Field created");
+        }
+    }
+    Ok(())
+}
+
+/// Edits a whole account as a TOML document in $EDITOR. The result is
+/// validated and the changes shown for confirmation before any are applied
+pub fn edit_account(app: &mut App, args: &EditArgs) -> Result<()> {
+    let name = args.account.as_ref().ok_or(Error::InsufficientArguments)?;
+    let account = app
+        .vault
+        .accounts()
+        .get(name)
+        .ok_or_else(|| Error::AccountNotFound(name.clone()))?;
+
+    let document = AccountDocument {
+        name: name.clone(),
+        folder: account.folder.clone(),
+        tags: account.tags.clone(),
+        fields: account.fields.clone().into_iter().collect(),
+    };
+    let initial = format!("{}{}", DOCUMENT_HEADER, toml::to_string(&document)?);
+
+    // reopen the editor on the user's text until it validates or they give up
+    let editor = editor_command(app);
+    let mut text = initial.clone();
+    let edited = loop {
+        text = edit_secure(&text, "toml", &editor)?;
+        if text == initial {
+            info!("Nothing was changed");
+            return Ok(());
+        }
+        match parse_document(&text) {
+            Ok(document) => break document,
+            Err(e) => {
+                if !confirm(&format!("{}, edit again?", e), true, &false)? {
+                    return Err(Error::Aborted.into());
+                }
+            }
+        }
+    };
+
+    let old = HashMap::from([(name.clone(), account.clone())]);
+    let edited_account = Account {
+        fields: edited.fields.into_iter().collect(),
+        tags: edited.tags,
+        folder: edited.folder.as_deref().and_then(normalize_folder),
+        created: account.created,
+        modified: account.modified,
+    };
+    let new = HashMap::from([(edited.name, edited_account)]);
+    if old == new {
+        info!("Nothing was changed");
+        return Ok(());
+    }
+    print_changes(&old, &new, args.show);
+
+    let force = &args.force;
+    let new_name = new.keys().next().unwrap();
+    if new_name != name
+        && app.vault.accounts().contains_key(new_name)
+        && !confirm(CONFIRM_OVERWRITE_PROMPT, false, force)?
+    {
+        return Err(Error::Aborted.into());
+    }
+    if !confirm("Apply these changes?", true, force)? {
+        return Err(Error::Aborted.into());
+    }
+
+    app.vault.accounts_mut().remove(name);
+    app.vault.accounts_mut().extend(new);
+    info!("Account edited");
+    Ok(())
+}
+
+/// parses an edited account document and validates every field
+fn parse_document(text: &str) -> Result<AccountDocument> {
+    let document: AccountDocument = toml::from_str(text)?;
+    if document.name.trim().is_empty() {
+        return Err(anyhow!("The account name is empty"));
+    }
+    for (name, field) in &document.fields {
+        field
+            .kind
+            .validate(&field.value)
+            .map_err(|e| anyhow!("Field {}: {}", name, e))?;
+    }
+    Ok(document)
+}
+
+/// picks a directory for plaintext temp files, memory backed where possible
+pub fn private_dir() -> Result<TempDir> {
+    let candidates = [
+        Some(PathBuf::from("/dev/shm")),
+        env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from),
+    ];
+    let base = match candidates.into_iter().flatten().find(|p| p.is_dir()) {
+        Some(p) => p,
+        None => {
+            warn!("No memory backed directory found, the temporary file may reach the disk");
+            env::temp_dir()
+        }
+    };
+
+    let mut builder = tempfile::Builder::new();
+    builder.prefix("passcli");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        builder.permissions(fs::Permissions::from_mode(0o700));
+    }
+    Ok(builder.tempdir_in(base)?)
+}
+
+/// the configured editor, then $VISUAL, $EDITOR and vi
+fn editor_command(app: &App) -> String {
+    app.config
+        .editor
+        .clone()
+        .or_else(|| env::var("VISUAL").ok())
+        .or_else(|| env::var("EDITOR").ok())
+        .unwrap_or_else(|| String::from("vi"))
+}
+
+fn run_editor(editor: &str, path: &Path) -> Result<()> {
+    let mut parts = editor.split_whitespace();
+    let program = parts
+        .next()
+        .ok_or_else(|| anyhow!("The editor command is empty"))?;
+
+    let status = Command::new(program).args(parts).arg(path).status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("Editor exited with {}", status))
+    }
+}
+
+/// overwrites a file with zeroes before removing it
+fn wipe(path: &Path) -> Result<()> {
+    if let Ok(meta) = fs::metadata(path) {
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.write_all(&vec![0; meta.len() as usize])?;
+        file.sync_all()?;
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod editor_tests {
+    use crate::Vault;
+
+    use super::*;
+
+    /// writes an editor script that notes the path it's given and replaces
+    /// the file with text, returning the script and where the path is noted
+    #[cfg(unix)]
+    fn fake_editor(dir: &Path, text: &str) -> (String, PathBuf) {
+        use std::os::unix::fs::PermissionsExt;
+        let seen = dir.join("seen");
+        let script = dir.join("editor.sh");
+        fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\necho \"$1\" > {}\nprintf '{}' > \"$1\"\n",
+                seen.display(),
+                text
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        (script.display().to_string(), seen)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_edit_secure() {
+        let dir = tempfile::tempdir().unwrap();
+        let (script, seen) = fake_editor(dir.path(), "line one\\nline two\\n");
+        assert_eq!(
+            edit_secure("", "txt", &script).unwrap(),
+            "line one\nline two\n"
+        );
+
+        // the plaintext and its private directory are gone afterwards
+        let edited = PathBuf::from(fs::read_to_string(&seen).unwrap().trim());
+        assert!(!edited.exists());
+        assert!(!edited.parent().unwrap().exists());
+
+        let mut app = App::test(Vault::create("", ""));
+        app.config.editor = Some(script);
+        let note = NoteArgs {
+            account: String::from("server"),
+            ..Default::default()
+        };
+        handle_note(&mut app, &note).unwrap();
+        let note = app.vault.get("server", "note").unwrap();
+        assert_eq!(note.kind, FieldKind::Note);
+        assert_eq!(note.value, "line one\nline two\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_wipe() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("plain");
+        let link = dir.path().join("link");
+        fs::write(&path, "secret").unwrap();
+        // a second link to the same data shows it was overwritten in place
+        fs::hard_link(&path, &link).unwrap();
+        wipe(&path).unwrap();
+        assert!(!path.exists());
+        assert_eq!(fs::read(&link).unwrap(), [0; 6]);
+    }
+
+    #[test]
+    fn test_parse_document() {
+        let document = parse_document(&format!(
+            "{}{}",
+            DOCUMENT_HEADER,
+            "name = \"mail\"\n\n[fields.user]\nkind = \"email\"\nvalue = \"me@example.com\"\n"
+        ))
+        .unwrap();
+        assert_eq!(document.name, "mail");
+        assert_eq!(document.fields["user"].kind, FieldKind::Email);
+
+        assert!(parse_document(
+            "name = \"mail\"\n[fields.user]\nkind = \"email\"\nvalue = \"me\"\n"
+        )
+        .is_err());
+        assert!(parse_document("name = \" \"\n").is_err());
+        assert!(parse_document("name = \"mail\"\nextra = 1\n").is_err());
+    }
+}
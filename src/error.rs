@@ -0,0 +1,134 @@
+//! Errors of the library and the exit codes the binary reports them with.
+//!
+//! | Code | Meaning                                            |
+//! |------|----------------------------------------------------|
+//! | 0    | Success                                            |
+//! | 1    | Any other failure                                  |
+//! | 2    | Invalid command line arguments                     |
+//! | 3    | Incorrect master password                          |
+//! | 4    | Vault file corrupt or truncated                    |
+//! | 5    | Vault version, cipher suite or compression unknown |
+//! | 6    | Account or field not found                         |
+//! | 7    | Reading or writing a file failed                   |
+//! | 8    | Cancelled at a prompt                              |
+//!
+//! The run operation exits with the status of the command it runs instead.
+use std::io;
+
+use crate::field::FieldKind;
+
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_PASSWORD: i32 = 3;
+pub const EXIT_CORRUPT: i32 = 4;
+pub const EXIT_VERSION: i32 = 5;
+pub const EXIT_NOT_FOUND: i32 = 6;
+pub const EXIT_IO: i32 = 7;
+pub const EXIT_ABORTED: i32 = 8;
+
+/// Errors reading, changing and writing vaults
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// the ciphertext didn't authenticate under the key derived from the
+    /// password, which is also what a ciphertext altered in place looks like
+    #[error("Incorrect password")]
+    IncorrectPassword,
+    /// the file or the decrypted payload doesn't parse
+    #[error("Vault is corrupt or truncated: {0}")]
+    Corrupt(String),
+    #[error("Unsupported vault version {found}, this build reads up to {supported}")]
+    UnsupportedVersion { found: u32, supported: u32 },
+    #[error("Unsupported cipher suite {0}")]
+    UnsupportedSuite(String),
+    #[error("Unsupported compression {0}")]
+    UnsupportedCompression(String),
+    #[error("Encryption failed")]
+    Crypto,
+    /// a payload too large for the vault format to record its length
+    #[error("Payload of {0} bytes is too large to write")]
+    TooLarge(usize),
+    #[error("Invalid key derivation parameters: {0}")]
+    KdfParams(String),
+    #[error("Account {0} not found")]
+    AccountNotFound(String),
+    #[error("Field {field} of account {account} not found")]
+    FieldNotFound { account: String, field: String },
+    #[error("Account {0} already exists")]
+    AccountExists(String),
+    #[error("Invalid {0:?} value")]
+    InvalidValue(FieldKind),
+    #[error("{0:?} fields can't be generated")]
+    NotGenerated(FieldKind),
+    #[error("Every character is disallowed")]
+    NoAllowedCharacters,
+    /// arguments clap can't check were missing or invalid
+    #[error("Insufficient arguments supplied")]
+    InsufficientArguments,
+    #[error("{0}")]
+    Usage(String),
+    /// the user declined to go on
+    #[error("Cancelled, nothing was changed")]
+    Aborted,
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// The exit code the binary reports the error with
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::IncorrectPassword => EXIT_PASSWORD,
+            Self::Corrupt(_) => EXIT_CORRUPT,
+            Self::UnsupportedVersion { .. }
+            | Self::UnsupportedSuite(_)
+            | Self::UnsupportedCompression(_) => EXIT_VERSION,
+            Self::AccountNotFound(_) | Self::FieldNotFound { .. } => EXIT_NOT_FOUND,
+            Self::InsufficientArguments | Self::Usage(_) => EXIT_USAGE,
+            Self::Aborted => EXIT_ABORTED,
+            Self::Io(e) if e.kind() == io::ErrorKind::Interrupted => EXIT_ABORTED,
+            Self::Io(_) => EXIT_IO,
+            _ => EXIT_FAILURE,
+        }
+    }
+}
+
+impl From<orion::errors::UnknownCryptoError> for Error {
+    fn from(_: orion::errors::UnknownCryptoError) -> Self {
+        Self::Crypto
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Corrupt(e.to_string())
+    }
+}
+
+impl From<base64::DecodeError> for Error {
+    fn from(e: base64::DecodeError) -> Self {
+        Self::Corrupt(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod error_tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(Error::IncorrectPassword.exit_code(), EXIT_PASSWORD);
+        assert_eq!(
+            Error::from(base64::DecodeError::InvalidPadding).exit_code(),
+            EXIT_CORRUPT
+        );
+        let interrupted = io::Error::from(io::ErrorKind::Interrupted);
+        assert_eq!(Error::from(interrupted).exit_code(), EXIT_ABORTED);
+        let missing = io::Error::from(io::ErrorKind::NotFound);
+        assert_eq!(Error::from(missing).exit_code(), EXIT_IO);
+        assert_eq!(Error::NoAllowedCharacters.exit_code(), EXIT_FAILURE);
+        assert_eq!(Error::InsufficientArguments.exit_code(), EXIT_USAGE);
+        assert_eq!(Error::TooLarge(usize::MAX).exit_code(), EXIT_FAILURE);
+    }
+}
@@ -0,0 +1,219 @@
+use base64::{engine::general_purpose, Engine};
+use clap::ValueEnum;
+use colored::*;
+use rand::prelude::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use time::{macros::format_description, Date};
+
+use crate::{account::Account, generate, Accounts, Error, Result};
+
+/// Accounts as stored before fields were typed
+pub type UntypedAccounts = HashMap<String, HashMap<String, String>>;
+
+/// What secret values are shown as when hidden
+pub const MASK: &str = "********";
+const BASE32: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldKind {
+    #[default]
+    Password,
+    /// plain value such as a host name or port
+    Text,
+    Username,
+    Email,
+    Url,
+    Note,
+    Totp,
+    Pin,
+    SshKey,
+    Date,
+    /// binary file stored base64 encoded
+    Attachment,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Field {
+    pub kind: FieldKind,
+    pub value: String,
+}
+
+impl Field {
+    /// creates a field after checking the value suits the kind
+    pub fn new(kind: FieldKind, value: String) -> Result<Self> {
+        kind.validate(&value)?;
+        Ok(Self { kind, value })
+    }
+
+    /// formats the value for listing, masking secrets if hide is set
+    pub fn display(&self, hide: &bool) -> String {
+        if self.kind == FieldKind::Attachment {
+            let size = general_purpose::STANDARD
+                .decode(&self.value)
+                .map_or(0, |d| d.len());
+            return format!("<attachment, {} bytes>", size).dimmed().to_string();
+        }
+        if *hide && self.kind.is_secret() {
+            return String::from(MASK);
+        }
+        match self.kind {
+            // continuation lines line up under the field name
+            FieldKind::Note | FieldKind::SshKey => self
+                .value
+                .trim_end()
+                .lines()
+                .collect::<Vec<_>>()
+                .join("\n        "),
+            FieldKind::Url => self.value.underline().to_string(),
+            _ => self.value.clone(),
+        }
+    }
+}
+
+impl FieldKind {
+    /// guesses the kind of an untyped field from its name
+    pub fn infer(name: &str) -> Self {
+        match name.to_lowercase().as_str() {
+            "user" | "username" | "login" => Self::Username,
+            "email" | "mail" => Self::Email,
+            "url" | "site" | "website" => Self::Url,
+            "note" | "notes" => Self::Note,
+            "totp" | "otp" | "2fa" => Self::Totp,
+            "pin" => Self::Pin,
+            "ssh" | "ssh_key" | "sshkey" => Self::SshKey,
+            "date" | "expiry" | "expires" => Self::Date,
+            _ => Self::Password,
+        }
+    }
+
+    /// whether values are masked when hiding
+    pub fn is_secret(&self) -> bool {
+        matches!(
+            self,
+            Self::Password | Self::Totp | Self::Pin | Self::SshKey | Self::Attachment
+        )
+    }
+
+    pub fn validate(&self, value: &str) -> Result<()> {
+        let valid = match self {
+            Self::Password | Self::Text | Self::Username | Self::Note => true,
+            Self::Email => value
+                .split_once('@')
+                .is_some_and(|(user, domain)| !user.is_empty() && domain.contains('.')),
+            Self::Url => value
+                .split_once("://")
+                .is_some_and(|(scheme, rest)| !scheme.is_empty() && !rest.is_empty()),
+            Self::Totp => {
+                !value.is_empty()
+                    && value
+                        .bytes()
+                        .filter(|c| !matches!(c, b' ' | b'='))
+                        .all(|c| BASE32.contains(&c.to_ascii_uppercase()))
+            }
+            Self::Pin => !value.is_empty() && value.bytes().all(|c| c.is_ascii_digit()),
+            Self::SshKey => {
+                value.starts_with("-----BEGIN")
+                    || value.starts_with("ssh-")
+                    || value.starts_with("ecdsa-")
+            }
+            Self::Date => Date::parse(value, format_description!("[year]-[month]-[day]")).is_ok(),
+            Self::Attachment => general_purpose::STANDARD.decode(value).is_ok(),
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(Error::InvalidValue(*self))
+        }
+    }
+
+    /// Generates a value of this kind. Lengths count characters for
+    /// passwords and TOTP secrets and digits for PINs
+    pub fn generate(
+        &self,
+        len: Option<usize>,
+        default_len: usize,
+        disallow: &str,
+    ) -> Result<String> {
+        match self {
+            Self::Password => generate::password(len.unwrap_or(default_len), disallow),
+            Self::Pin => Ok(random_from(b"0123456789", len.unwrap_or(6))),
+            Self::Totp => Ok(random_from(BASE32, len.unwrap_or(32))),
+            _ => Err(Error::NotGenerated(*self)),
+        }
+    }
+}
+
+fn random_from(alphabet: &[u8], len: usize) -> String {
+    let mut rng = thread_rng();
+    (0..len)
+        .map(|_| alphabet[rng.gen_range(0..alphabet.len())] as char)
+        .collect()
+}
+
+/// Types the fields of accounts from before typed fields, going by their
+/// names. Values are kept as they are even if they wouldn't validate
+pub fn migrate(accounts: UntypedAccounts) -> Accounts {
+    accounts
+        .into_iter()
+        .map(|(name, fields)| {
+            let fields = fields
+                .into_iter()
+                .map(|(field, value)| {
+                    let kind = FieldKind::infer(&field);
+                    (field, Field { kind, value })
+                })
+                .collect();
+            (name, Account::new(fields))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod field_tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate() {
+        let untyped = HashMap::from([(
+            String::from("mail"),
+            HashMap::from([
+                (String::from("pass"), String::from("hunter2")),
+                (String::from("user"), String::from("me")),
+                (String::from("url"), String::from("not a url")),
+            ]),
+        )]);
+
+        let account = &migrate(untyped)["mail"];
+
+        assert_eq!(account.fields["pass"].kind, FieldKind::Password);
+        assert_eq!(account.fields["user"].kind, FieldKind::Username);
+        assert_eq!(account.fields["url"].kind, FieldKind::Url);
+        assert_eq!(account.fields["url"].value, "not a url");
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Field::new(FieldKind::Email, "me@example.com".into()).is_ok());
+        assert!(Field::new(FieldKind::Email, "me".into()).is_err());
+        assert!(Field::new(FieldKind::Url, "https://example.com".into()).is_ok());
+        assert!(Field::new(FieldKind::Url, "example.com".into()).is_err());
+        assert!(Field::new(FieldKind::Pin, "0420".into()).is_ok());
+        assert!(Field::new(FieldKind::Pin, "04a0".into()).is_err());
+        assert!(Field::new(FieldKind::Date, "2024-02-29".into()).is_ok());
+        assert!(Field::new(FieldKind::Date, "2023-02-29".into()).is_err());
+        assert!(Field::new(FieldKind::Totp, "JBSW Y3DP EHPK 3PXP".into()).is_ok());
+        assert!(Field::new(FieldKind::Totp, "JBSW1".into()).is_err());
+    }
+
+    #[test]
+    fn test_generate() {
+        let pin = FieldKind::Pin.generate(Some(8), 16, "").unwrap();
+        assert!(FieldKind::Pin.validate(&pin).is_ok() && pin.len() == 8);
+        let totp = FieldKind::Totp.generate(None, 16, "").unwrap();
+        assert!(FieldKind::Totp.validate(&totp).is_ok() && totp.len() == 32);
+        assert!(FieldKind::Email.generate(None, 16, "").is_err());
+    }
+}
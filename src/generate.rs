@@ -0,0 +1,70 @@
+use rand::prelude::{thread_rng, Rng};
+use std::iter::repeat_with;
+
+use crate::{Error, Result};
+
+/// Generates a password with ascii values between 33-126 barring any
+/// characters that are disallowed, see [`allowed_chars`]
+pub fn password(len: usize, disallow: &str) -> Result<String> {
+    let allowed_chars = allowed_chars(disallow);
+    if allowed_chars.is_empty() {
+        return Err(Error::NoAllowedCharacters);
+    }
+
+    let mut rng = thread_rng();
+    Ok(
+        repeat_with(|| allowed_chars[rng.gen_range(0..allowed_chars.len())])
+            .take(len)
+            .map(|c| c as char)
+            .collect(),
+    )
+}
+
+/// Bits of entropy of a generated password
+pub fn entropy(len: usize, disallow: &str) -> f64 {
+    len as f64 * (allowed_chars(disallow).len() as f64).log2()
+}
+
+/// The ascii values between 33-126 that aren't disallowed. Classes of
+/// characters are named symbol, digit, uppercase and lowercase, separated
+/// from other classes and characters by ",,"
+pub fn allowed_chars(disallow: &str) -> Vec<u8> {
+    let disallow: Vec<u8> = disallow
+        .split(",,")
+        .flat_map(|s| match s {
+            "symbol" => (33..=47)
+                .chain(58..=64)
+                .chain(91..=96)
+                .chain(123..=126)
+                .collect::<Vec<u8>>(),
+            "digit" => (48..=57).collect(),
+            "uppercase" => (65..=90).collect(),
+            "lowercase" => (97..=122).collect(),
+            _ => s.as_bytes().to_vec(),
+        })
+        .collect();
+
+    (33..=126).filter(|&c| !disallow.contains(&c)).collect()
+}
+
+#[cfg(test)]
+mod generate_tests {
+    use super::*;
+
+    #[test]
+    fn test_password() {
+        let password = password(24, "symbol,,digit,,xyz").unwrap();
+        assert_eq!(password.len(), 24);
+        assert!(password
+            .bytes()
+            .all(|c| c.is_ascii_alphabetic() && !b"xyz".contains(&c)));
+        assert!(matches!(
+            super::password(8, "symbol,,digit,,uppercase,,lowercase"),
+            Err(Error::NoAllowedCharacters)
+        ));
+        assert_eq!(
+            entropy(10, "symbol,,uppercase,,lowercase"),
+            10.0 * 10f64.log2()
+        );
+    }
+}
@@ -0,0 +1,267 @@
+//! Git credential helper speaking git's `get`, `store` and `erase` protocol.
+//!
+//! Configure it with `git config credential.helper '!passcli git-credential'`.
+//! Credentials are kept in an account named after the host, or
+//! `username@host` when the host's account belongs to another user, holding
+//! a `url`, a `user` and the default field for the password.
+use anyhow::{anyhow, Result};
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+use crate::{
+    account::Account,
+    config::GitCredentialArgs,
+    field::{Field, FieldKind},
+    Accounts, App, Error,
+};
+
+/// The attributes git describes a credential with
+#[derive(Debug, Default, PartialEq)]
+struct Request {
+    protocol: Option<String>,
+    host: Option<String>,
+    path: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+impl Request {
+    /// Reads key=value lines up to a blank line or the end of input. A url
+    /// attribute fills in whatever the other attributes leave out
+    fn parse(input: impl BufRead) -> Result<Self> {
+        let mut request = Self::default();
+        for line in input.lines() {
+            let line = line?;
+            if line.is_empty() {
+                break;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Malformed credential attribute {}", line))?;
+            let slot = match key {
+                "protocol" => &mut request.protocol,
+                "host" => &mut request.host,
+                "path" => &mut request.path,
+                "username" => &mut request.username,
+                "password" => &mut request.password,
+                "url" => {
+                    request.fill_from_url(value);
+                    continue;
+                }
+                // capabilities, expiry and the like aren't used
+                _ => continue,
+            };
+            *slot = Some(value.to_string());
+        }
+        Ok(request)
+    }
+
+    fn fill_from_url(&mut self, url: &str) {
+        let Some((protocol, rest)) = url.split_once("://") else {
+            return;
+        };
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let (user, host) = match authority.rsplit_once('@') {
+            Some((user, host)) => (Some(user), host),
+            None => (None, authority),
+        };
+        self.protocol.get_or_insert_with(|| protocol.to_string());
+        self.host.get_or_insert_with(|| host.to_string());
+        if !path.is_empty() {
+            self.path.get_or_insert_with(|| path.to_string());
+        }
+        if let Some(user) = user {
+            self.username.get_or_insert_with(|| user.to_string());
+        }
+    }
+
+    /// the host with the path git was asked to consider, if any
+    fn target(&self) -> Option<String> {
+        let host = self.host.as_ref()?;
+        Some(match &self.path {
+            Some(path) => format!("{}/{}", host, path),
+            None => host.clone(),
+        })
+    }
+
+    fn url(&self) -> Option<String> {
+        Some(format!("{}://{}", self.protocol.as_ref()?, self.target()?))
+    }
+}
+
+/// Answers git's credential requests. Nothing is printed when no credential
+/// matches so git moves on to its other helpers
+pub fn handle_git_credential(app: &mut App, args: &GitCredentialArgs) -> Result<()> {
+    let action = &args.action;
+    let request = Request::parse(io::stdin().lock())?;
+    let default_field = app.config.default_field.clone();
+
+    match action.as_str() {
+        "get" => {
+            if let Some(response) = get(app.vault.accounts(), &request, &default_field) {
+                io::stdout().write_all(response.as_bytes())?;
+            }
+            Ok(())
+        }
+        "store" => store(app.vault.accounts_mut(), &request, &default_field),
+        "erase" => {
+            erase(app.vault.accounts_mut(), &request, &default_field);
+            Ok(())
+        }
+        _ => Err(Error::Usage(format!("Unknown git credential action {}", action)).into()),
+    }
+}
+
+/// name of the account holding the request's credential
+fn find<'a>(accounts: &'a Accounts, request: &Request) -> Option<&'a String> {
+    let target = request.target()?;
+    let candidates = match &request.username {
+        Some(user) => vec![format!("{}@{}", user, target), target],
+        None => vec![target],
+    };
+
+    candidates.into_iter().find_map(|name| {
+        let (name, account) = accounts.get_key_value(&name)?;
+        let value = |field: &str| account.fields.get(field).map(|f| f.value.as_str());
+        let same_protocol = match (value("url"), &request.protocol) {
+            (Some(url), Some(protocol)) => url.starts_with(&format!("{}://", protocol)),
+            _ => true,
+        };
+        let same_user = match (value("user"), &request.username) {
+            (Some(user), Some(username)) => user == username,
+            _ => true,
+        };
+        (same_protocol && same_user).then_some(name)
+    })
+}
+
+fn get(accounts: &Accounts, request: &Request, default_field: &str) -> Option<String> {
+    let account = &accounts[find(accounts, request)?];
+    let password = &account.fields.get(default_field)?.value;
+    let username = account
+        .fields
+        .get("user")
+        .map(|f| &f.value)
+        .or(request.username.as_ref());
+
+    let mut response = String::new();
+    if let Some(username) = username {
+        response.push_str(&format!("username={}\n", username));
+    }
+    response.push_str(&format!("password={}\n", password));
+    Some(response)
+}
+
+fn store(accounts: &mut Accounts, request: &Request, default_field: &str) -> Result<()> {
+    let (Some(target), Some(password)) = (request.target(), &request.password) else {
+        return Ok(());
+    };
+    let name = match find(accounts, request) {
+        Some(name) => name.clone(),
+        None if accounts.contains_key(&target) => match &request.username {
+            Some(user) => format!("{}@{}", user, target),
+            None => return Ok(()),
+        },
+        None => target,
+    };
+
+    let account = accounts
+        .entry(name)
+        .or_insert_with(|| Account::new(HashMap::new()));
+    let mut fields = vec![(default_field, FieldKind::Password, password.clone())];
+    if let Some(user) = &request.username {
+        fields.push(("user", FieldKind::Username, user.clone()));
+    }
+    if let Some(url) = request.url() {
+        fields.push(("url", FieldKind::Url, url));
+    }
+    for (field, kind, value) in fields {
+        account
+            .fields
+            .insert(field.to_string(), Field::new(kind, value)?);
+    }
+    Ok(())
+}
+
+/// removes the password if it's the one git rejected, keeping the rest
+fn erase(accounts: &mut Accounts, request: &Request, default_field: &str) {
+    let Some(name) = find(accounts, request).cloned() else {
+        return;
+    };
+    let fields = &mut accounts.get_mut(&name).unwrap().fields;
+    let rejected = match (&request.password, fields.get(default_field)) {
+        (Some(rejected), Some(stored)) => *rejected == stored.value,
+        _ => true,
+    };
+    if rejected {
+        fields.remove(default_field);
+    }
+}
+
+#[cfg(test)]
+mod git_credential_tests {
+    use super::*;
+
+    fn request(input: &str) -> Request {
+        Request::parse(input.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            request("url=https://me@example.com/repo.git\npassword=x\n\nhost=ignored\n"),
+            Request {
+                protocol: Some(String::from("https")),
+                host: Some(String::from("example.com")),
+                path: Some(String::from("repo.git")),
+                username: Some(String::from("me")),
+                password: Some(String::from("x")),
+            }
+        );
+        assert!(Request::parse("nonsense\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_store_get_erase() {
+        let mut accounts = Accounts::new();
+        let stored = "protocol=https\nhost=github.com\nusername=me\npassword=token\n";
+        store(&mut accounts, &request(stored), "pass").unwrap();
+        let other = "protocol=https\nhost=github.com\nusername=bot\npassword=bot token\n";
+        store(&mut accounts, &request(other), "pass").unwrap();
+
+        assert_eq!(
+            accounts["github.com"].fields["url"].value,
+            "https://github.com"
+        );
+        assert!(accounts.contains_key("bot@github.com"));
+
+        let get = |input| get(&accounts, &request(input), "pass");
+        assert_eq!(
+            get("protocol=https\nhost=github.com\n").as_deref(),
+            Some("username=me\npassword=token\n")
+        );
+        assert_eq!(
+            get("protocol=https\nhost=github.com\nusername=bot\n").as_deref(),
+            Some("username=bot\npassword=bot token\n")
+        );
+        assert_eq!(get("protocol=http\nhost=github.com\n"), None);
+        assert_eq!(get("protocol=https\nhost=gitlab.com\n"), None);
+
+        // only the rejected password is erased
+        erase(
+            &mut accounts,
+            &request("host=github.com\npassword=old\n"),
+            "pass",
+        );
+        assert!(accounts["github.com"].fields.contains_key("pass"));
+        erase(
+            &mut accounts,
+            &request("host=github.com\npassword=token\n"),
+            "pass",
+        );
+        assert!(!accounts["github.com"].fields.contains_key("pass"));
+        assert!(accounts["github.com"].fields.contains_key("user"));
+    }
+}
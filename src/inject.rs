@@ -0,0 +1,93 @@
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use std::{
+    fs,
+    io::{self, Read, Write},
+};
+
+use crate::{config::InjectArgs, crypt::create_private, reference::lookup, Accounts, App};
+
+const SCHEME: &str = "passcli://";
+
+/// Renders a template, replacing every `{{ passcli://account/field }}` with
+/// the field's value. Reads --input or stdin and writes --output, readable
+/// by the owner only, or stdout. Nothing is written if a reference is missing
+pub fn handle_inject(app: &App, args: &InjectArgs) -> Result<()> {
+    let template = match &args.input {
+        Some(path) => fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?,
+        None => {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text)?;
+            text
+        }
+    };
+    let rendered = render(&template, app.vault.accounts(), &app.config.default_field)?;
+
+    match &args.output {
+        Some(path) => {
+            create_private(path)?.write_all(rendered.as_bytes())?;
+            info!("Rendered template written to {}", path.display());
+        }
+        None => io::stdout().write_all(rendered.as_bytes())?,
+    }
+    Ok(())
+}
+
+/// replaces secret references, leaving any other {{ }} placeholders as they are
+fn render(template: &str, accounts: &Accounts, default_field: &str) -> Result<String> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start..].find("}}") else {
+            break;
+        };
+        let inner = rest[start + 2..start + len].trim();
+        out.push_str(&rest[..start]);
+        match inner.strip_prefix(SCHEME) {
+            Some(reference) => {
+                let offset = template.len() - rest.len() + start;
+                let line = template[..offset].matches('\n').count() + 1;
+                let field = lookup(accounts, reference, default_field)
+                    .map_err(|e| anyhow!("Line {}: {}", line, e))?;
+                out.push_str(&field.value);
+            }
+            None => out.push_str(&rest[start..start + len + 2]),
+        }
+        rest = &rest[start + len + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod inject_tests {
+    use std::collections::HashMap;
+
+    use crate::field::migrate;
+
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let accounts = migrate(HashMap::from([(
+            String::from("db"),
+            HashMap::from([
+                (String::from("pass"), String::from("hunter2")),
+                (String::from("user"), String::from("admin")),
+            ]),
+        )]));
+
+        let rendered = render(
+            "user={{passcli://db/user}}\npass={{ passcli://db }}\nname={{ app_name }}\n",
+            &accounts,
+            "pass",
+        )
+        .unwrap();
+        assert_eq!(rendered, "user=admin\npass=hunter2\nname={{ app_name }}\n");
+        assert_eq!(render("a {{ b", &accounts, "pass").unwrap(), "a {{ b");
+
+        let missing = render("ok\n{{ passcli://db/token }}", &accounts, "pass");
+        assert!(missing.unwrap_err().to_string().starts_with("Line 2:"));
+    }
+}
@@ -0,0 +1,173 @@
+use anyhow::Result;
+use log::info;
+use orion::kdf::Salt;
+use passcli::suite::MAX_MEMORY;
+use std::time::{Duration, Instant};
+
+use crate::{
+    config::{KdfBenchArgs, PassConfig, RekeyArgs},
+    App, KdfParams, Suite,
+};
+
+/// fewest passes Argon2i is used with
+const MIN_ITERATIONS: u32 = 3;
+/// least memory in KiB kdf-bench recommends
+const MIN_MEMORY: u32 = 1 << 13;
+
+/// Times key derivation with the configured parameters, then finds the most
+/// memory and passes that fit the target unlock time. Memory is doubled while
+/// the fewest passes fit twice over, or halved until they fit, then passes
+/// are added since time grows linearly with them
+pub fn handle_kdf_bench(args: &KdfBenchArgs, config: &PassConfig) -> Result<()> {
+    let target = Duration::from_millis(args.target);
+    let (suite, current) = (config.suite, config.kdf());
+    println!("Configured:  {}", measure(suite, &current)?);
+
+    let mut params = KdfParams {
+        iterations: MIN_ITERATIONS,
+        memory: args.memory.unwrap_or(current.memory),
+        parallelism: args.parallelism.unwrap_or(current.parallelism),
+    };
+    params.validate()?;
+    let mut elapsed = time(suite, &params)?;
+    // memory costs an attacker more than passes do
+    while elapsed * 2 <= target && params.memory <= MAX_MEMORY / 2 {
+        params.memory *= 2;
+        elapsed = time(suite, &params)?;
+    }
+    while elapsed > target && params.memory / 2 >= MIN_MEMORY.max(8 * params.parallelism) {
+        println!("Too slow:    {}", describe(&params, elapsed));
+        params.memory /= 2;
+        elapsed = time(suite, &params)?;
+    }
+
+    let per_pass = elapsed.as_secs_f64() / f64::from(params.iterations);
+    params.iterations = ((target.as_secs_f64() / per_pass) as u32).max(MIN_ITERATIONS);
+    println!("Recommended: {}", measure(suite, &params)?);
+    println!(
+        "Apply with: passcli rekey --iterations {} --memory {} --parallelism {}",
+        params.iterations, params.memory, params.parallelism
+    );
+    Ok(())
+}
+
+/// Sets another suite or key derivation parameters for the vault to be
+/// written with, the ones not given being kept. Both are recorded in the
+/// vault's header, so the backups written before stay readable as they are
+pub fn handle_rekey(app: &mut App, args: &RekeyArgs) -> Result<()> {
+    let (suite, current) = (app.vault.cipher_suite(), *app.vault.kdf_params());
+    let new_suite = args.suite.unwrap_or(suite);
+    let params = KdfParams {
+        iterations: args.iterations.unwrap_or(current.iterations),
+        memory: args.memory.unwrap_or(current.memory),
+        parallelism: args.parallelism.unwrap_or(current.parallelism),
+    };
+    params.validate()?;
+    if (new_suite, params) == (suite, current) {
+        info!("Suite and parameters unchanged, nothing was changed");
+        return Ok(());
+    }
+
+    app.vault.set_suite(new_suite);
+    app.vault.set_kdf(params);
+    info!(
+        "Vault rekeyed with {}, unlocking takes {:.2?}",
+        new_suite,
+        time(new_suite, &params)?
+    );
+    Ok(())
+}
+
+/// times a key derivation with the parameters and describes it
+fn measure(suite: Suite, params: &KdfParams) -> Result<String> {
+    Ok(describe(params, time(suite, params)?))
+}
+
+fn describe(params: &KdfParams, elapsed: Duration) -> String {
+    format!(
+        "{} iterations, {} KiB, {} lanes: {:.2?}",
+        params.iterations, params.memory, params.parallelism, elapsed
+    )
+}
+
+/// time a key derivation with the parameters takes
+fn time(suite: Suite, params: &KdfParams) -> Result<Duration> {
+    let salt = Salt::default();
+    let start = Instant::now();
+    suite.derive_key("kdf-bench", salt.as_ref(), params)?;
+    Ok(start.elapsed())
+}
+
+#[cfg(test)]
+mod kdf_tests {
+    use crate::Vault;
+
+    use super::*;
+
+    /// parameters quick enough to derive keys with in tests
+    fn small() -> KdfParams {
+        KdfParams {
+            iterations: MIN_ITERATIONS,
+            memory: MIN_MEMORY,
+            parallelism: 1,
+        }
+    }
+
+    #[test]
+    fn test_kdf_bench() {
+        let config = PassConfig {
+            kdf_memory: MIN_MEMORY,
+            ..Default::default()
+        };
+        let bench = KdfBenchArgs {
+            target: 1,
+            ..Default::default()
+        };
+        handle_kdf_bench(&bench, &config).unwrap();
+
+        // memory over the limit is refused before anything is timed
+        let huge = KdfBenchArgs {
+            memory: Some(u32::MAX),
+            ..bench
+        };
+        assert!(handle_kdf_bench(&huge, &config).is_err());
+    }
+
+    #[test]
+    fn test_rekey() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("passwd");
+        let mut app = App::test(Vault::create(&path, "pw").kdf(small()).backup_count(0));
+        app.vault.save().unwrap();
+
+        // nothing given, nothing changed
+        handle_rekey(&mut app, &RekeyArgs::default()).unwrap();
+        assert_eq!(app.vault.cipher_suite(), Suite::default());
+        assert_eq!(app.vault.kdf_params(), &small());
+
+        let rekey = RekeyArgs {
+            suite: Some(Suite::Argon2idAes256Gcm),
+            iterations: Some(MIN_ITERATIONS + 1),
+            ..Default::default()
+        };
+        handle_rekey(&mut app, &rekey).unwrap();
+        app.vault.save().unwrap();
+        let vault = Vault::open(&path).unwrap().unlock("pw").unwrap();
+        assert_eq!(vault.cipher_suite(), Suite::Argon2idAes256Gcm);
+        assert_eq!(
+            vault.kdf_params(),
+            &KdfParams {
+                iterations: MIN_ITERATIONS + 1,
+                ..small()
+            }
+        );
+
+        // parameters outside the limits are refused and the vault kept
+        let weak = RekeyArgs {
+            iterations: Some(1),
+            ..Default::default()
+        };
+        assert!(handle_rekey(&mut app, &weak).is_err());
+        assert_eq!(app.vault.kdf_params().iterations, MIN_ITERATIONS + 1);
+    }
+}
@@ -0,0 +1,27 @@
+//! Reading and writing passcli vaults.
+//!
+//! A [`Vault`] is opened from its file, unlocked with the master password and
+//! saved back after changing its accounts. The file format lives in [`crypt`],
+//! its ciphers in [`suite`], payload compression in [`compression`] and the
+//! password generator in [`generate`]. The passcli binary is a command line
+//! frontend on top of this crate.
+use std::collections::HashMap;
+
+pub mod account;
+pub mod compression;
+pub mod crypt;
+pub mod error;
+pub mod field;
+pub mod generate;
+pub mod suite;
+pub mod vault;
+
+pub use account::Account;
+pub use compression::Compression;
+pub use error::{Error, Result};
+pub use field::{Field, FieldKind};
+pub use suite::{KdfParams, Suite};
+pub use vault::{LockedVault, Vault};
+
+/// Accounts of a vault by name
+pub type Accounts = HashMap<String, Account>;
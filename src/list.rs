@@ -0,0 +1,104 @@
+use anyhow::Result;
+use colored::*;
+use std::{collections::BTreeMap, fmt::Write};
+use time::{macros::format_description, OffsetDateTime};
+
+use crate::{
+    account::{sorted, Account},
+    config::{FilterArgs, ListArgs},
+    pager::page,
+    Accounts, App,
+};
+
+/// Writes accounts as a table of name, number of fields and the date of
+/// the last change
+pub fn write_table(out: &mut String, accounts: &[(&String, &Account)]) -> Result<()> {
+    let width = accounts
+        .iter()
+        .map(|(name, _)| name.chars().count())
+        .chain(["ACCOUNT".len()])
+        .max()
+        .unwrap_or_default();
+    writeln!(
+        out,
+        "{}  {}  {}",
+        format!("{:width$}", "ACCOUNT").bold(),
+        "FIELDS".bold(),
+        "MODIFIED".bold()
+    )?;
+
+    let format = format_description!("[year]-[month]-[day]");
+    for (name, account) in accounts {
+        let modified = match account.modified {
+            Some(time) => OffsetDateTime::from_unix_timestamp(time)?.format(&format)?,
+            None => String::from("-"),
+        };
+        writeln!(
+            out,
+            "{}  {:>6}  {}",
+            format!("{:width$}", name).magenta(),
+            account.fields.len(),
+            modified.dimmed()
+        )?;
+    }
+    Ok(())
+}
+
+/// accounts passing the tag and folder filters
+pub fn filtered<'a>(
+    accounts: &'a Accounts,
+    filter: &'a FilterArgs,
+) -> impl Iterator<Item = (&'a String, &'a Account)> {
+    accounts
+        .iter()
+        .filter(|(_, a)| a.matches(&filter.tag, filter.folder.as_deref()))
+}
+
+#[derive(Default)]
+struct Folder<'a> {
+    folders: BTreeMap<&'a str, Folder<'a>>,
+    accounts: Vec<(&'a str, &'a Account)>,
+}
+
+/// Prints accounts as a tree of folders, filtered by tag and folder. Folders
+/// are sorted by name and the accounts in each by the sort argument
+pub fn handle_list(app: &App, args: &ListArgs) -> Result<()> {
+    let listing = &args.listing;
+    let accounts = sorted(
+        filtered(app.vault.accounts(), &args.filter),
+        listing.sort.unwrap_or_default(),
+    );
+    let mut out = String::new();
+    if listing.table {
+        write_table(&mut out, &accounts)?;
+        return page(&out, listing.pager);
+    }
+
+    let mut root = Folder::default();
+    for (name, account) in accounts {
+        let mut node = &mut root;
+        for part in account.folder.iter().flat_map(|f| f.split('/')) {
+            node = node.folders.entry(part).or_default();
+        }
+        node.accounts.push((name, account));
+    }
+    write_folder(&mut out, &root, 0)?;
+    page(&out, listing.pager)
+}
+
+fn write_folder(out: &mut String, folder: &Folder, depth: usize) -> Result<()> {
+    let indent = "    ".repeat(depth);
+    for (name, child) in &folder.folders {
+        writeln!(out, "{}{}/", indent, name.blue().bold())?;
+        write_folder(out, child, depth + 1)?;
+    }
+    for (name, account) in &folder.accounts {
+        let tags = account.tags.iter().cloned().collect::<Vec<_>>().join(", ");
+        if tags.is_empty() {
+            writeln!(out, "{}{}", indent, name.magenta())?;
+        } else {
+            writeln!(out, "{}{} [{}]", indent, name.magenta(), tags.dimmed())?;
+        }
+    }
+    Ok(())
+}
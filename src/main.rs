@@ -2,124 +2,213 @@
 use anyhow::{anyhow, Result};
 use clap::Parser;
 use colored::*;
-use config::{Args, Ops, PassConfig};
-use crypt::{read_encrypted_file, write_encrypted_file};
+use config::{AddArgs, Args, Cli, Command, EditArgs, GenArgs, Ops, PassConfig, RmArgs, ShowArgs};
 use dialoguer::{Confirm, Input, Password};
 use log::{debug, error, info, LevelFilter};
-use rand::prelude::{thread_rng, Rng};
+use passcli::{
+    account, crypt, error, field, generate, vault, Account, Accounts, Compression, Error,
+    KdfParams, Suite, Vault,
+};
 use simplelog::{ColorChoice, CombinedLogger, Config, TermLogger, TerminalMode};
 use std::{
-    collections::{hash_map::Entry, HashMap},
-    fs,
-    iter::repeat_with,
+    collections::{hash_map::Entry, BTreeMap, HashMap},
+    fmt::Write,
+    fs, io,
     path::PathBuf,
     process::exit,
 };
 
-mod config;
-mod crypt;
+use field::{Field, FieldKind};
 
-type Account = HashMap<String, String>;
-type Accounts = HashMap<String, Account>;
+mod attachment;
+mod audit;
+mod check;
+mod completion;
+mod config;
+mod diff;
+mod editor;
+mod git_credential;
+mod inject;
+mod kdf;
+mod list;
+mod merge;
+mod pager;
+mod reference;
+mod run;
+#[cfg(unix)]
+mod ssh_agent;
+mod sync;
+mod template;
 
 #[derive(Debug)]
 struct App {
     args: Args,
     config: PassConfig,
-    path: PathBuf,
-    master_pass: String,
-    passwords: Accounts,
+    vault: Vault,
     interactive: bool,
+    // exit status of the command started by the run operation
+    status: i32,
 }
 
 impl App {
-    /// Initializes the application by parsing arguments and the config file
+    /// Initializes the application from parsed arguments and the config file
     /// if present, then handling the password file. Also initialises logger
-    fn new() -> Result<Self> {
-        let args = Args::parse();
-        let config = PassConfig::new()?;
-
-        CombinedLogger::init(vec![TermLogger::new(
-            if args.quiet {
-                LevelFilter::Off
-            } else {
-                LevelFilter::from(config.log_level)
-            },
-            Config::default(),
-            TerminalMode::Mixed,
-            ColorChoice::Auto,
-        )])
-        .unwrap();
-
-        let path = if let Some(p) = args.path.as_ref() {
-            p.clone()
-        } else if let Some(p) = config.default_path.as_ref() {
-            p.clone()
-        } else if let Some(p) = dirs::data_dir() {
-            p.join("passcli/passwd")
-        } else {
-            return Err(anyhow!(
-                "No target path specified and no data directory found"
-            ));
-        };
+    fn new(args: Args, config: PassConfig) -> Result<Self> {
+        init_logger(&args, &config);
+        let path = vault_path(&args, &config)?;
+        let master_pass = master_password(&args, &config)?;
 
-        let master_pass = if let Some(p) = args.pass.as_ref() {
-            p.clone()
-        } else if let Some(p) = config.default_pass.as_ref() {
-            p.clone()
-        } else {
-            prompt_password(MASTER_PASSWORD_INPUT_PROMPT, false, &args.force)?
-        };
-
-        if path.exists() {
+        let vault = if path.exists() {
             debug!("File found at target path");
-            if let Ok(passwords) = read_encrypted_file(&master_pass, &path, &config.kdf_iterations)
-            {
-                debug!("File read successfully");
-                Ok(Self {
-                    args,
-                    config,
-                    path,
-                    master_pass,
-                    passwords,
-                    interactive: false,
-                })
-            } else {
-                Err(anyhow!("Incorrect password\n"))
-            }
+            let vault = Vault::open(path)?.kdf(config.kdf()).unlock(&master_pass)?;
+            debug!("File read successfully");
+            vault
         } else {
             info!("File not found, new file will be created");
             if let Some(parent) = path.parent() {
                 fs::create_dir_all(parent)?;
             }
             let master_pass = prompt_password("Create master password", true, &false)?;
-            Ok(Self {
-                args,
-                config,
-                path,
-                master_pass,
-                passwords: HashMap::new(),
-                interactive: false,
-            })
+            Vault::create(path, &master_pass)
+                .suite(config.suite)
+                .kdf(config.kdf())
+        };
+        Ok(Self {
+            vault: vault
+                .compression(config.compression)
+                .armor(config.armor)
+                .backup_count(config.backup_count),
+            args,
+            config,
+            interactive: false,
+            status: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+impl App {
+    /// an app on an already unlocked vault, with default arguments and config
+    fn test(vault: Vault) -> Self {
+        Self {
+            args: Args::default(),
+            config: PassConfig::default(),
+            vault,
+            interactive: false,
+            status: 0,
         }
     }
 }
 
+/// logs to the terminal at the configured level
+fn init_logger(args: &Args, config: &PassConfig) {
+    CombinedLogger::init(vec![TermLogger::new(
+        // git reads the credential helper's stdout
+        if args.quiet || matches!(args.command, Command::GitCredential(_)) {
+            LevelFilter::Off
+        } else {
+            LevelFilter::from(config.log_level)
+        },
+        Config::default(),
+        TerminalMode::Mixed,
+        ColorChoice::Auto,
+    )])
+    .unwrap();
+}
+
+/// the vault given as an argument, else the configured one, else the default
+fn vault_path(args: &Args, config: &PassConfig) -> Result<PathBuf> {
+    if let Some(p) = args.path.as_ref() {
+        Ok(p.clone())
+    } else if let Some(p) = config.default_path.as_ref() {
+        Ok(p.clone())
+    } else if let Some(p) = dirs::data_dir() {
+        Ok(p.join("passcli/passwd"))
+    } else {
+        Err(anyhow!(
+            "No target path specified and no data directory found"
+        ))
+    }
+}
+
+/// the master password given as an argument, else the configured one, else
+/// prompted for
+fn master_password(args: &Args, config: &PassConfig) -> Result<String> {
+    if let Some(p) = args.pass.as_ref() {
+        Ok(p.clone())
+    } else if let Some(p) = config.default_pass.as_ref() {
+        Ok(p.clone())
+    } else {
+        Ok(prompt_password(
+            MASTER_PASSWORD_INPUT_PROMPT,
+            false,
+            &false,
+        )?)
+    }
+}
+
 fn main() {
-    match run() {
+    match run(Cli::parse(), PassConfig::path()) {
         Ok(ret) => exit(ret),
         Err(err) => {
-            eprintln!("Exiting with error:\n{}", err);
-            exit(1);
+            eprintln!("Exiting with error:\n{:#}", err);
+            exit(exit_code(&err));
         }
     }
 }
 
-fn run() -> Result<i32> {
-    let mut app = App::new()?;
+/// the exit code of the first cause that has one, see [`passcli::error`]
+fn exit_code(err: &anyhow::Error) -> i32 {
+    for cause in err.chain() {
+        if let Some(e) = cause.downcast_ref::<Error>() {
+            return e.exit_code();
+        }
+        if let Some(e) = cause.downcast_ref::<io::Error>() {
+            return if e.kind() == io::ErrorKind::Interrupted {
+                error::EXIT_ABORTED
+            } else {
+                error::EXIT_IO
+            };
+        }
+    }
+    error::EXIT_FAILURE
+}
 
-    match app.args.operation {
-        Some(Ops::Interactive) => {
+fn run(cli: Cli, config_path: Option<PathBuf>) -> Result<i32> {
+    // completion needs neither the config nor the vault
+    let args = Args::from(cli);
+    match &args.command {
+        Command::Complete(complete) => return completion::handle_complete(complete).map(|_| 0),
+        Command::Completions(completions) => {
+            return completion::handle_completions(completions).map(|_| 0)
+        }
+        _ => {}
+    }
+
+    // generating and benchmarking don't open the vault
+    let config = PassConfig::load(config_path)?;
+    match &args.command {
+        Command::Gen(gen) => {
+            return handle_gen(gen, &config, &mut io::stdout(), &mut io::stderr()).map(|_| 0);
+        }
+        Command::KdfBench(bench) => return kdf::handle_kdf_bench(bench, &config).map(|_| 0),
+        // a vault that fails to unlock can still be checked
+        Command::Check(check) => {
+            init_logger(&args, &config);
+            let (path, password) = (
+                vault_path(&args, &config)?,
+                master_password(&args, &config)?,
+            );
+            return check::handle_check(&path, &password, check, &config);
+        }
+        _ => {}
+    }
+
+    let mut app = App::new(args, config)?;
+    completion::update_cache(&app);
+
+    match app.args.command {
+        Command::Interactive => {
             info!("Interactive mode initialised (q or quit to exit)");
             app.interactive = true;
             loop {
@@ -127,45 +216,111 @@ fn run() -> Result<i32> {
                 if ["quit", "q"].contains(&cmd.as_str()) {
                     break;
                 }
-                let mut args = vec!["passcli "];
-                args.append(&mut cmd.split_whitespace().collect());
-                app.args = Args::parse_from(args);
+                let words = ["passcli"].into_iter().chain(cmd.split_whitespace());
+                match Cli::try_parse_from(words) {
+                    Ok(cli) => app.args = Args::from(cli),
+                    Err(e) => {
+                        e.print()?;
+                        continue;
+                    }
+                }
 
                 if let Err(e) = handle_cmd(&mut app) {
                     error!("{}", e);
                 }
 
-                // write file after every command in case of arg parsing error
-                if let Some(Ops::Print) | None = app.args.operation {
-                } else {
-                    write_encrypted_file(&app)?;
+                // write file after every command
+                if let Some(op) = app.args.command.mutating_op() {
+                    save(&app, &op)?;
                 }
             }
             Ok(0)
         }
         _ => {
             handle_cmd(&mut app)?;
-            if let Some(Ops::Print) | None = app.args.operation {
-                Ok(0)
+            if let Some(op) = app.args.command.mutating_op() {
+                save(&app, &op)
             } else {
-                write_encrypted_file(&app)
+                Ok(app.status)
             }
         }
     }
 }
 
+/// writes the vault and commits it when git sync is enabled
+fn save(app: &App, op: &Ops) -> Result<i32> {
+    app.vault.save()?;
+    completion::update_cache(app);
+    if app.config.git_sync {
+        sync::commit(app, &format!("passcli: {:?}", op).to_lowercase())?;
+    }
+    Ok(0)
+}
+
 fn handle_cmd(app: &mut App) -> Result<()> {
-    match &app.args.operation {
-        Some(Ops::Add) => handle_add(app)?,
-        Some(Ops::Remove) => handle_remove(app)?,
-        Some(Ops::Edit) => handle_edit(app)?,
-        Some(Ops::Print) | None => handle_print(app)?,
-        Some(Ops::Interactive) => {}
+    // handlers take the vault mutably alongside their own arguments
+    let command = app.args.command.clone();
+    // merged accounts keep the times they carry
+    let before = command
+        .mutating_op()
+        .filter(|op| !matches!(op, Ops::Sync | Ops::Merge))
+        .map(|_| app.vault.accounts().clone());
+
+    let result = match &command {
+        Command::Add(args) => handle_add(app, args),
+        Command::Rm(args) => handle_remove(app, args),
+        Command::Edit(args) => handle_edit(app, args),
+        Command::Show(args) => handle_print(app, args),
+        Command::Sync(args) => sync::handle_sync(app, args),
+        Command::Merge(args) => merge::handle_merge(app, args),
+        Command::Diff(args) => diff::handle_diff(app, args),
+        Command::Log(args) => audit::handle_log(app, args),
+        Command::Note(args) => editor::handle_note(app, args),
+        Command::Attach(args) => attachment::handle_attach(app, args),
+        Command::Extract(args) => attachment::handle_extract(app, args),
+        Command::List(args) => list::handle_list(app, args),
+        Command::Run(args) => run::handle_run(app, args),
+        Command::Inject(args) => inject::handle_inject(app, args),
+        Command::GitCredential(args) => git_credential::handle_git_credential(app, args),
+        #[cfg(unix)]
+        Command::SshAgent(args) => ssh_agent::handle_ssh_agent(app, args),
+        #[cfg(not(unix))]
+        Command::SshAgent(_) => Err(anyhow!("The SSH agent needs Unix sockets")),
+        Command::Interactive => Ok(()),
+        Command::KdfBench(args) => kdf::handle_kdf_bench(args, &app.config),
+        Command::Rekey(args) => kdf::handle_rekey(app, args),
+        Command::Gen(args) => handle_gen(args, &app.config, &mut io::stdout(), &mut io::stderr()),
+        Command::Complete(args) => completion::handle_complete(args),
+        Command::Completions(args) => completion::handle_completions(args),
+        Command::Check(args) => {
+            let path = app.vault.path();
+            check::handle_check(path, app.vault.password(), args, &app.config).map(|_| ())
+        }
+    };
+
+    if let Some(before) = before {
+        account::stamp(&before, app.vault.accounts_mut());
+    }
+
+    let audited = if app.config.audit_log {
+        audit_cmd(app, &command, result.is_ok())
+    } else {
+        Ok(())
+    };
+    // the handler's error is the one worth reporting
+    result.and(audited)
+}
+
+/// Records the command in the audit log. A new log's key is kept in the
+/// vault, which is written here unless the command writes it anyway
+fn audit_cmd(app: &mut App, command: &Command, ok: bool) -> Result<()> {
+    let created = audit::record(app, command, ok)?;
+    if created && !(ok && command.mutating_op().is_some()) {
+        app.vault.save()?;
     }
     Ok(())
 }
 
-const ARGUMENT_NOT_FOUND: &str = "Argument not found: ";
 const PROPERTY_INPUT_PROMPT: &str = "Enter new property";
 const NEW_PASSWORD_INPUT_PROMPT: &str = "Enter new password";
 const MASTER_PASSWORD_INPUT_PROMPT: &str = "Enter master password";
@@ -175,107 +330,135 @@ const ACCOUNT: &str = "account ";
 const FIELD: &str = "field ";
 
 /// Add or edit account fields, an empty account can also be added
-fn handle_add(app: &mut App) -> Result<()> {
+fn handle_add(app: &mut App, args: &AddArgs) -> Result<()> {
+    if let Some(template) = &args.template {
+        return template::handle_template_add(app, args, template);
+    }
+
     // create references for relevant fields
-    let (account_arg, field_arg, gen_arg, force_arg, hide, interactive, disallow, passwords) = (
-        &app.args.account,
-        app.args.field.as_ref().unwrap_or(&app.config.default_field),
+    let (account, field_arg, gen_arg, force_arg, hide, interactive, disallow, passwords) = (
+        &args.account,
+        args.field.as_ref().unwrap_or(&app.config.default_field),
         // left as arg ref to check if it was passed
-        &app.args.gen,
-        &app.args.force,
-        &app.args.hide,
+        &args.value.gen,
+        &args.force,
+        &args.value.hide,
         &app.interactive,
-        app.args
+        args.value
             .disallow
             .as_ref()
             .unwrap_or(&app.config.default_disallow),
-        &mut app.passwords,
+        app.vault.accounts_mut(),
     );
 
-    // refactor to allow entering account and password via prompting
-    if account_arg.is_none() {
-        return Err(anyhow!("Insufficient arguments supplied"));
-    }
-    let account = account_arg.as_ref().unwrap();
+    // explicit kind, else the kind of the field being overwritten, else guessed from the name
+    let kind = args
+        .value
+        .kind
+        .or_else(|| Some(passwords.get(account)?.fields.get(field_arg)?.kind))
+        .unwrap_or_else(|| FieldKind::infer(field_arg));
 
     // check if password gen argument was specified and if so override the value
     let value = if let Some(gen) = gen_arg {
-        debug!("Password generated");
-        &Some(Some(gen_passwd(
-            &gen.unwrap_or(app.config.default_gen),
+        debug!("Value generated");
+        &Some(Some(generate_value(
+            kind,
+            *gen,
+            &app.config,
             disallow,
             hide,
-        )))
+        )?))
     } else {
-        &app.args.value
+        &args.value.value
     };
 
     if let Some(account_map) = passwords.get_mut(account) {
-        match account_map.entry(field_arg.clone()) {
+        if args.meta.apply(account_map) {
+            info!("Account tags and folder updated");
+        }
+        match account_map.fields.entry(field_arg.clone()) {
             Entry::Occupied(mut entry) => {
                 // confirm edit if field is already extant
                 if confirm(CONFIRM_OVERWRITE_PROMPT, false, force_arg)? {
-                    entry.insert(unwrap_or_new_password(value, force_arg)?);
+                    entry.insert(unwrap_or_field(value, kind, force_arg)?);
                     info!("Field edited");
                 } else {
-                    info!("Nothing was changed");
+                    return Err(Error::Aborted.into());
                 }
             }
             Entry::Vacant(entry) => {
-                entry.insert(unwrap_or_new_password(value, force_arg)?);
+                entry.insert(unwrap_or_field(value, kind, force_arg)?);
                 info!("Field created");
             }
         }
     } else {
-        passwords.insert(
-            account.clone(),
-            HashMap::from([(field_arg.clone(), unwrap_or_new_password(value, force_arg)?)]),
-        );
+        let mut account_map = Account::new(HashMap::from([(
+            field_arg.clone(),
+            unwrap_or_field(value, kind, force_arg)?,
+        )]));
+        args.meta.apply(&mut account_map);
+        passwords.insert(account.clone(), account_map);
         info!("Account and field created");
     }
 
     Ok(())
 }
 
-fn handle_print(app: &App) -> Result<()> {
-    let (account, field, hide, passwords, all) = (
-        &app.args.account,
-        app.args.field.as_ref().unwrap_or(&app.config.default_field),
-        &app.args.hide,
-        &app.passwords,
-        &app.args.all_fields,
+fn handle_print(app: &App, args: &ShowArgs) -> Result<()> {
+    let (account, field, hide, passwords, all, listing) = (
+        &args.account,
+        args.field.as_ref().unwrap_or(&app.config.default_field),
+        &args.hide,
+        app.vault.accounts(),
+        &args.all_fields,
+        &args.listing,
     );
 
     if account.is_none() {
-        for (k, v) in passwords.iter() {
-            print_account(k, v, hide)?;
+        let accounts = account::sorted(
+            list::filtered(passwords, &args.filter),
+            listing.sort.unwrap_or_default(),
+        );
+        let mut out = String::new();
+        if listing.table {
+            list::write_table(&mut out, &accounts)?;
+        } else {
+            for (k, v) in accounts {
+                write_account(&mut out, k, v, hide)?;
+            }
         }
-        return Ok(());
+        return pager::page(&out, listing.pager);
     }
 
     let account = account.as_ref().unwrap();
 
     if let Some(account_map) = passwords.get(account) {
         if *all {
-            print_account(account, account_map, hide)
-        } else if let Some(password) = account_map.get(field) {
+            let mut out = String::new();
+            write_account(&mut out, account, account_map, hide)?;
+            pager::page(&out, listing.pager)
+        } else if let Some(password) = account_map.fields.get(field) {
             // if non interactive then have entire stdout be just the password
             if !app.interactive {
-                println!("{}", password);
+                println!("{}", password.value);
             } else {
-                print!("{}", password);
+                print!("{}", password.value);
             }
             Ok(())
         } else {
-            Err(anyhow!("{}{}", ARGUMENT_NOT_FOUND, field))
+            Err(field_not_found(account, field))
         }
     } else {
-        Err(anyhow!("{}{}", ARGUMENT_NOT_FOUND, account))
+        Err(Error::AccountNotFound(account.clone()).into())
     }
 }
 
 /// Operation to edit properties, requires specific arguments
-fn handle_edit(app: &mut App) -> Result<()> {
+fn handle_edit(app: &mut App, args: &EditArgs) -> Result<()> {
+    if args.editor {
+        return editor::edit_account(app, args);
+    }
+
     let (
         account_arg,
         field_arg,
@@ -284,111 +467,124 @@ fn handle_edit(app: &mut App) -> Result<()> {
         force_arg,
         new_password_arg,
         gen_arg,
+        kind_arg,
         interactive,
         disallow,
-        passwords,
-        master_pass,
+        vault,
     ) = (
-        &app.args.account,
-        &app.args.field,
-        &app.args.hide,
-        &app.args.value,
-        &app.args.force,
-        &app.args.new_password,
-        &app.args.gen,
+        &args.account,
+        &args.field,
+        &args.value.hide,
+        &args.value.value,
+        &args.force,
+        &args.new_password,
+        &args.value.gen,
+        &args.value.kind,
         &app.interactive,
-        app.args
+        args.value
             .disallow
             .as_ref()
             .unwrap_or(&app.config.default_disallow),
-        &mut app.passwords,
-        &mut app.master_pass,
+        &mut app.vault,
     );
 
     // Edit master pass if no account arg passed
     if account_arg.is_none() {
-        if confirm("Confirm editing master password", false, force_arg)? {
-            *master_pass = unwrap_or_new_password(value_arg, force_arg)?;
+        if !confirm("Confirm editing master password", false, force_arg)? {
+            return Err(Error::Aborted.into());
         }
+        vault.set_password(&unwrap_or_new_password(value_arg, force_arg)?);
         return Ok(());
     }
+    let passwords = vault.accounts_mut();
 
     let account = account_arg.as_ref().unwrap();
 
-    // prepare genned password to simplify nested code
-    let genned_password = gen_arg.map(|gen_arg_value| {
-        gen_passwd(
-            &gen_arg_value.unwrap_or(app.config.default_gen),
-            disallow,
-            hide,
-        )
-    });
-
     // If user specifies only an account or field, that will be edited
     // They can pass -v with a value to edit those values without prompting
     // In order to edit a password they have to specify the account and field with
     // -g or --new-password. An empty --new-password will reult in a prompt
-    if let Some((account_key, mut account_map)) = passwords.remove_entry(account) {
-        if let Some(field) = field_arg {
-            if let (Some(_), _) | (_, Some(_)) = (new_password_arg, gen_arg) {
-                info!("Editing password");
-                let prev_password = get_or_error(field, &account_map)?;
-                if !*hide {
-                    println!("Previous password is {}", prev_password);
-                }
-                // get value for new password, prioritising -g
-                let new_password = if let Some(v) = genned_password {
-                    v
-                } else {
-                    unwrap_or_new_password(new_password_arg, force_arg)?
-                };
-                account_map.insert(field.clone(), new_password);
-            } else {
-                info!("Editing field name");
-                if let Some(old_value) = account_map.remove(field) {
-                    let new_key = &unwrap_or_input(value_arg)?;
-                    if account_map.contains_key(new_key) {
-                        if confirm(CONFIRM_OVERWRITE_PROMPT, false, force_arg)? {
-                            account_map.insert(new_key.clone(), old_value);
-                        }
-                    } else {
-                        account_map.insert(new_key.clone(), old_value);
-                    }
-                } else {
-                    return Err(anyhow!("{}{}", ARGUMENT_NOT_FOUND, FIELD));
-                }
+    // Passing --kind with a field changes its kind instead
+    if let Some(field) = field_arg {
+        let account_map = passwords
+            .get_mut(account)
+            .ok_or_else(|| Error::AccountNotFound(account.clone()))?;
+
+        if let (Some(_), _) | (_, Some(_)) = (new_password_arg, gen_arg) {
+            info!("Editing password");
+            let prev = get_or_error(account, field, account_map)?;
+            if !*hide {
+                println!("Previous password is {}", prev.value);
             }
-            // Reinsert the modified account_map
-            passwords.insert(account_key, account_map);
+            // get value for new password, prioritising -g
+            let new_password = if let Some(gen) = gen_arg {
+                generate_value(prev.kind, *gen, &app.config, disallow, hide)?
+            } else {
+                unwrap_or_new_password(new_password_arg, force_arg)?
+            };
+            account_map
+                .fields
+                .insert(field.clone(), Field::new(prev.kind, new_password)?);
+        } else if let Some(kind) = kind_arg {
+            info!("Editing field kind");
+            let prev = get_or_error(account, field, account_map)?;
+            account_map
+                .fields
+                .insert(field.clone(), Field::new(*kind, prev.value)?);
         } else {
-            info!("Editing account name");
+            info!("Editing field name");
+            let fields = &mut account_map.fields;
+            if !fields.contains_key(field) {
+                return Err(field_not_found(account, field));
+            }
             let new_key = &unwrap_or_input(value_arg)?;
-            // we know at this point that the account exists
-            if passwords.contains_key(new_key) {
-                if confirm(CONFIRM_OVERWRITE_PROMPT, false, force_arg)? {
-                    passwords.insert(new_key.clone(), account_map);
-                }
-            } else {
+            if fields.contains_key(new_key) && !confirm(CONFIRM_OVERWRITE_PROMPT, false, force_arg)?
+            {
+                return Err(Error::Aborted.into());
+            }
+            let old_value = fields.remove(field).unwrap();
+            fields.insert(new_key.clone(), old_value);
+        }
+    } else if let Some(account_map) = passwords
+        .get_mut(account)
+        .filter(|_| args.meta.given() || !args.untag.is_empty())
+    {
+        info!("Editing account tags and folder");
+        args.meta.apply(account_map);
+        for tag in &args.untag {
+            account_map.tags.remove(tag);
+        }
+    } else if let Some((account_key, account_map)) = passwords.remove_entry(account) {
+        info!("Editing account name");
+        let new_key = &unwrap_or_input(value_arg)?;
+        // we know at this point that the account exists
+        if passwords.contains_key(new_key) {
+            if confirm(CONFIRM_OVERWRITE_PROMPT, false, force_arg)? {
                 passwords.insert(new_key.clone(), account_map);
+            } else {
+                passwords.insert(account_key, account_map);
+                return Err(Error::Aborted.into());
             }
+        } else {
+            passwords.insert(new_key.clone(), account_map);
         }
     } else {
-        return Err(anyhow!("{}{}", ARGUMENT_NOT_FOUND, ACCOUNT));
+        return Err(Error::AccountNotFound(account.clone()).into());
     }
 
     Ok(())
 }
 
-fn handle_remove(app: &mut App) -> Result<()> {
+fn handle_remove(app: &mut App, args: &RmArgs) -> Result<()> {
     let (account_arg, field_arg, force_arg, passwords) = (
-        &app.args.account,
-        &app.args.field,
-        &app.args.force,
-        &mut app.passwords,
+        &args.account,
+        &args.field,
+        &args.force,
+        app.vault.accounts_mut(),
     );
 
     if account_arg.is_none() {
-        if confirm(
+        if !(confirm(
             "No account specified, would you like to delete the entire database?",
             false,
             force_arg,
@@ -396,9 +592,10 @@ fn handle_remove(app: &mut App) -> Result<()> {
             "Are you sure you would like to delete all your passwords?",
             false,
             force_arg,
-        )? {
-            *passwords = HashMap::new();
+        )?) {
+            return Err(Error::Aborted.into());
         }
+        *passwords = HashMap::new();
         return Ok(());
     }
     let account = account_arg.as_ref().unwrap();
@@ -406,18 +603,21 @@ fn handle_remove(app: &mut App) -> Result<()> {
     if let Some((account_key, mut account_map)) = passwords.remove_entry(account) {
         match field_arg {
             Some(field) => {
-                if account_map.contains_key(field) {
+                if account_map.fields.contains_key(field) {
                     if confirm(
                         &format!("{}{}{}", CONFIRM_DELETION_PROMPT, FIELD, field),
                         false,
                         force_arg,
                     )? {
-                        account_map.remove(field);
+                        account_map.fields.remove(field);
+                    } else {
+                        passwords.insert(account_key, account_map);
+                        return Err(Error::Aborted.into());
                     }
                 } else {
                     // Reinsert account before returning error
                     passwords.insert(account_key, account_map);
-                    return Err(anyhow!("{}{}", ARGUMENT_NOT_FOUND, FIELD));
+                    return Err(field_not_found(account, field));
                 }
                 // Reinsert the modified account_map
                 passwords.insert(account_key, account_map);
@@ -429,11 +629,12 @@ fn handle_remove(app: &mut App) -> Result<()> {
                     force_arg,
                 )? {
                     passwords.insert(account_key, account_map);
+                    return Err(Error::Aborted.into());
                 }
             }
         }
     } else {
-        return Err(anyhow!("{}{}", ARGUMENT_NOT_FOUND, ACCOUNT));
+        return Err(Error::AccountNotFound(account.clone()).into());
     }
 
     Ok(())
@@ -442,10 +643,20 @@ fn handle_remove(app: &mut App) -> Result<()> {
 // convenience functions follow
 
 /// gets value from an Account or returns an error
-fn get_or_error(field: &str, map: &Account) -> Result<String> {
-    map.get(field)
+fn get_or_error(account: &str, field: &str, map: &Account) -> Result<Field> {
+    map.fields
+        .get(field)
         .cloned()
-        .ok_or_else(|| anyhow!(ARGUMENT_NOT_FOUND))
+        .ok_or_else(|| field_not_found(account, field))
+}
+
+/// the error for a missing field of an account
+fn field_not_found(account: &str, field: &str) -> anyhow::Error {
+    Error::FieldNotFound {
+        account: account.to_string(),
+        field: field.to_string(),
+    }
+    .into()
 }
 
 /// double unwraps the value or new_password argument or prompts user for input
@@ -469,20 +680,44 @@ fn unwrap_or_new_password(
     }
 }
 
+/// unwraps a value from args or prompts for one, hidden if the kind is secret
+fn unwrap_or_field(value: &Option<Option<String>>, kind: FieldKind, force: &bool) -> Result<Field> {
+    let value = if kind.is_secret() {
+        unwrap_or_new_password(value, force)?
+    } else {
+        unwrap_or_input(value)?
+    };
+    Ok(Field::new(kind, value)?)
+}
+
 /// shortened dialoguer user input prompt
 fn user_input(prompt: &str) -> Result<String, dialoguer::Error> {
     Input::new().with_prompt(prompt).interact_text()
 }
 
-/// prints Account aesthetically, passwords hidden if specified
-fn print_account(name: &str, account: &Account, hide: &bool) -> Result<()> {
-    println!("{}:", name.magenta());
-    for (k, v) in account.iter() {
-        if *hide {
-            println!("    {}", k.green());
-        } else {
-            println!("    {}: {}", k.green(), v);
-        }
+/// writes Account aesthetically with fields sorted, secret fields masked if
+/// specified
+fn write_account(out: &mut String, name: &str, account: &Account, hide: &bool) -> Result<()> {
+    let folder = account.folder.as_ref().map(|f| format!(" {}/", f));
+    let tags = account.tags.iter().cloned().collect::<Vec<_>>().join(", ");
+    if tags.is_empty() {
+        writeln!(
+            out,
+            "{}:{}",
+            name.magenta(),
+            folder.unwrap_or_default().blue()
+        )?;
+    } else {
+        writeln!(
+            out,
+            "{}:{} [{}]",
+            name.magenta(),
+            folder.unwrap_or_default().blue(),
+            tags.dimmed()
+        )?;
+    }
+    for (k, v) in account.fields.iter().collect::<BTreeMap<_, _>>() {
+        writeln!(out, "    {}: {}", k.green(), v.display(hide))?;
     }
     Ok(())
 }
@@ -512,36 +747,124 @@ fn prompt_password(prompt: &str, confirm: bool, force: &bool) -> Result<String,
     }
 }
 
-/// generates a password with ascii values between 33-126
-/// barring any characters that are disallowed
-fn gen_passwd(len: &usize, disallow: &str, hide: &bool) -> String {
-    let mut rng = thread_rng();
-
-    let disallow: Vec<u8> = disallow
-        .split(",,")
-        .flat_map(|s| match s {
-            "symbol" => (33..=47)
-                .chain(58..=64)
-                .chain(91..=96)
-                .chain(123..=126)
-                .collect::<Vec<u8>>(),
-            "digit" => (48..=57).collect(),
-            "uppercase" => (65..=90).collect(),
-            "lowercase" => (97..=122).collect(),
-            _ => s.as_bytes().to_vec(),
-        })
-        .collect();
+/// Writes generated passwords to out, one per line, and their entropy to err
+/// if asked. Works without the vault so nothing is read or written
+fn handle_gen(
+    args: &GenArgs,
+    config: &PassConfig,
+    out: &mut impl io::Write,
+    err: &mut impl io::Write,
+) -> Result<()> {
+    let len = args.len.unwrap_or(config.default_gen);
+    let disallow = args.disallow.as_ref().unwrap_or(&config.default_disallow);
+
+    for _ in 0..args.count {
+        writeln!(out, "{}", generate::password(len, disallow)?)?;
+    }
+    if args.entropy {
+        writeln!(
+            err,
+            "{:.1} bits of entropy",
+            generate::entropy(len, disallow)
+        )?;
+    }
+    Ok(())
+}
 
-    let allowed_chars: Vec<u8> = (33..=126).filter(|&c| !disallow.contains(&c)).collect();
+/// generates a value of a kind, printing it unless hidden
+fn generate_value(
+    kind: FieldKind,
+    len: Option<usize>,
+    config: &PassConfig,
+    disallow: &str,
+    hide: &bool,
+) -> Result<String> {
+    let value = kind.generate(len, config.default_gen, disallow)?;
+    if !*hide {
+        println!("{}", value);
+    }
+    Ok(value)
+}
 
-    let password: String = repeat_with(|| allowed_chars[rng.gen_range(0..allowed_chars.len())])
-        .take(*len)
-        .map(|c| c as char)
-        .collect();
+#[cfg(test)]
+mod main_tests {
+    use super::*;
+
+    #[test]
+    fn test_gen() {
+        let args = GenArgs {
+            len: Some(20),
+            disallow: Some(String::from("abc")),
+            count: 3,
+            entropy: true,
+        };
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        handle_gen(&args, &PassConfig::default(), &mut out, &mut err).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let passwords: Vec<&str> = out.lines().collect();
+        assert_eq!(passwords.len(), 3);
+        assert!(passwords
+            .iter()
+            .all(|p| p.len() == 20 && !p.contains(['a', 'b', 'c'])));
+        let entropy = format!("{:.1} bits of entropy\n", generate::entropy(20, "abc"));
+        assert_eq!(String::from_utf8(err).unwrap(), entropy);
+    }
 
-    if !*hide {
-        println!("{}", password);
+    #[test]
+    fn test_audit_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("passwd");
+        let mut app = App::test(Vault::create(&path, "pw"));
+        app.args.command = Command::List(Default::default());
+        app.vault.save().unwrap();
+
+        // the key of the log a read starts is written to the vault, so the
+        // next read continues the log instead of moving it aside
+        handle_cmd(&mut app).unwrap();
+        let stored = Vault::open(&path).unwrap().unlock("pw").unwrap();
+        assert_eq!(stored.audit().len(), 1);
+        handle_cmd(&mut app).unwrap();
+        let names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert!(!names
+            .iter()
+            .any(|n| n.to_string_lossy().starts_with("passwd.log.")));
+
+        // the handler's error comes before any from the log
+        fs::create_dir(dir.path().join("blocked")).unwrap();
+        app.vault = Vault::create(dir.path().join("blocked/passwd"), "pw");
+        fs::create_dir(dir.path().join("blocked/passwd.log")).unwrap();
+        app.args.command = Command::Show(ShowArgs {
+            account: Some(String::from("missing")),
+            ..Default::default()
+        });
+        let err = handle_cmd(&mut app).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(Error::AccountNotFound(_))
+        ));
     }
 
-    password
+    #[test]
+    fn test_gen_without_vault() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vaults/passwd");
+        let words = [
+            "passcli",
+            "--path",
+            path.to_str().unwrap(),
+            "gen",
+            "-n",
+            "2",
+        ];
+        // the config is still read, kept out of the user's config directory
+        let config = dir.path().join("config/passcli.toml");
+        let cli = Cli::try_parse_from(words).unwrap();
+        assert_eq!(run(cli, Some(config.clone())).unwrap(), 0);
+        assert!(config.exists());
+        assert!(!path.parent().unwrap().exists());
+    }
 }
@@ -0,0 +1,321 @@
+use anyhow::{Context, Result};
+use log::info;
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::Path,
+};
+
+use crate::{
+    config::MergeArgs,
+    confirm,
+    crypt::{read_encrypted_file, Payload},
+    vault::merge_states,
+    Account, Accounts, App, ACCOUNT, FIELD,
+};
+
+/// Merges another copy of the vault into this one. Without a common base
+/// nothing counts as removed, so the result is the union of both copies
+pub fn handle_merge(app: &mut App, args: &MergeArgs) -> Result<()> {
+    let read = |path: &Path| {
+        read_encrypted_file(app.vault.password(), path, &app.config.kdf())
+            .with_context(|| format!("Failed to read vault {}", path.display()))
+    };
+    let theirs = read(&args.vault)?;
+    let base = match &args.base {
+        Some(path) => read(path)?,
+        None => Payload::default(),
+    };
+
+    *app.vault.accounts_mut() = merge_accounts(
+        &base.accounts,
+        app.vault.accounts(),
+        &theirs.accounts,
+        &args.force,
+    )?;
+    merge_states(app.vault.audit_mut(), theirs.audit);
+    info!("Vaults merged");
+    Ok(())
+}
+
+/// Three-way merges two diverged copies of a vault against their common base.
+/// Changes made on only one side are applied automatically, the user is only
+/// prompted when both sides changed the same field or one side removed what
+/// the other changed. Forcing keeps our side of every conflict
+pub fn merge_accounts(
+    base: &Accounts,
+    ours: &Accounts,
+    theirs: &Accounts,
+    force: &bool,
+) -> Result<Accounts> {
+    let mut merged = HashMap::new();
+    let names: BTreeSet<&String> = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .collect();
+
+    for name in names {
+        let (b, o, t) = (base.get(name), ours.get(name), theirs.get(name));
+        let account = match (o, t) {
+            (Some(o), Some(t)) => Some(merge_account(
+                name,
+                b.unwrap_or(&Account::default()),
+                o,
+                t,
+                force,
+            )?),
+            _ => resolve(b, o, t, &format!("{}{}", ACCOUNT, name), force)?.cloned(),
+        };
+        if let Some(account) = account {
+            merged.insert(name.clone(), account);
+        }
+    }
+
+    Ok(merged)
+}
+
+/// merges the fields, tags and folder of an account present on both sides
+fn merge_account(
+    name: &str,
+    base: &Account,
+    ours: &Account,
+    theirs: &Account,
+    force: &bool,
+) -> Result<Account> {
+    let mut merged = Account::default();
+    let fields: BTreeSet<&String> = base
+        .fields
+        .keys()
+        .chain(ours.fields.keys())
+        .chain(theirs.fields.keys())
+        .collect();
+
+    for field in fields {
+        let label = format!("{}{} of {}{}", FIELD, field, ACCOUNT, name);
+        let (b, o, t) = (
+            base.fields.get(field),
+            ours.fields.get(field),
+            theirs.fields.get(field),
+        );
+        if let Some(value) = resolve(b, o, t, &label, force)? {
+            merged.fields.insert(field.clone(), value.clone());
+        }
+    }
+
+    // a tag is only ever added or removed, so tags never conflict
+    let tags: BTreeSet<&String> = base
+        .tags
+        .iter()
+        .chain(&ours.tags)
+        .chain(&theirs.tags)
+        .collect();
+    for tag in tags {
+        let side = |account: &'_ Account| account.tags.contains(tag).then_some(tag);
+        if let Some(tag) = resolve(side(base), side(ours), side(theirs), "", force)? {
+            merged.tags.insert(tag.clone());
+        }
+    }
+
+    merged.created = ours.created.into_iter().chain(theirs.created).min();
+    merged.modified = ours.modified.max(theirs.modified);
+
+    let label = format!("folder of {}{}", ACCOUNT, name);
+    merged.folder = resolve(
+        Some(&base.folder),
+        Some(&ours.folder),
+        Some(&theirs.folder),
+        &label,
+        force,
+    )?
+    .cloned()
+    .flatten();
+
+    Ok(merged)
+}
+
+/// picks the side that changed relative to base, prompting if both did
+fn resolve<'a, T: PartialEq>(
+    base: Option<&'a T>,
+    ours: Option<&'a T>,
+    theirs: Option<&'a T>,
+    label: &str,
+    force: &bool,
+) -> Result<Option<&'a T>> {
+    if ours == theirs || theirs == base {
+        return Ok(ours);
+    }
+    if ours == base {
+        return Ok(theirs);
+    }
+
+    // true conflict, both sides diverged from base differently
+    let keep_ours = match (ours, theirs) {
+        (Some(_), Some(_)) => confirm(
+            &format!("Conflicting edits to the {}, keep the local value?", label),
+            true,
+            force,
+        )?,
+        (Some(_), None) => confirm(
+            &format!(
+                "The {} was removed in the other copy but changed locally, keep it?",
+                label
+            ),
+            true,
+            force,
+        )?,
+        (None, _) => confirm(
+            &format!(
+                "The {} was removed locally but changed in the other copy, remove it?",
+                label
+            ),
+            true,
+            force,
+        )?,
+    };
+    info!("Conflict on the {} resolved", label);
+
+    Ok(if keep_ours { ours } else { theirs })
+}
+
+#[cfg(test)]
+mod merge_tests {
+    use crate::{
+        field::{Field, FieldKind},
+        Vault,
+    };
+
+    use super::*;
+
+    fn account(fields: &[(&str, &str)]) -> Account {
+        Account::new(
+            fields
+                .iter()
+                .map(|(k, v)| {
+                    let field = Field {
+                        kind: FieldKind::infer(k),
+                        value: v.to_string(),
+                    };
+                    (k.to_string(), field)
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_merge_disjoint_changes() {
+        let base = HashMap::from([
+            (
+                String::from("mail"),
+                account(&[("pass", "a"), ("user", "me")]),
+            ),
+            (String::from("bank"), account(&[("pass", "b")])),
+        ]);
+        let mut ours = base.clone();
+        ours.get_mut("mail")
+            .unwrap()
+            .fields
+            .get_mut("pass")
+            .unwrap()
+            .value = "a2".into();
+        ours.insert("new local".into(), account(&[("pass", "c")]));
+        let mut theirs = base.clone();
+        theirs.get_mut("mail").unwrap().fields.remove("user");
+        theirs.remove("bank");
+
+        let merged = merge_accounts(&base, &ours, &theirs, &false).unwrap();
+
+        assert_eq!(
+            merged,
+            HashMap::from([
+                (String::from("mail"), account(&[("pass", "a2")])),
+                (String::from("new local"), account(&[("pass", "c")])),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_merge_conflict_forced_keeps_ours() {
+        let base = HashMap::from([(String::from("mail"), account(&[("pass", "a")]))]);
+        let ours = HashMap::from([(String::from("mail"), account(&[("pass", "ours")]))]);
+        let theirs = HashMap::from([(
+            String::from("mail"),
+            account(&[("pass", "theirs"), ("user", "me")]),
+        )]);
+
+        let merged = merge_accounts(&base, &ours, &theirs, &true).unwrap();
+
+        assert_eq!(
+            merged,
+            HashMap::from([(
+                String::from("mail"),
+                account(&[("pass", "ours"), ("user", "me")])
+            )])
+        );
+    }
+
+    #[test]
+    fn test_handle_merge() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, accounts: &Accounts| {
+            let path = dir.path().join(name);
+            let mut vault = Vault::create(&path, "pw").backup_count(0);
+            *vault.accounts_mut() = accounts.clone();
+            vault.save().unwrap();
+            path
+        };
+        let base = HashMap::from([
+            (
+                String::from("mail"),
+                account(&[("pass", "a"), ("user", "me")]),
+            ),
+            (String::from("bank"), account(&[("pass", "b")])),
+        ]);
+        let mut ours = base.clone();
+        ours.get_mut("mail")
+            .unwrap()
+            .fields
+            .get_mut("pass")
+            .unwrap()
+            .value = "a2".into();
+        let mut theirs = base.clone();
+        theirs.get_mut("mail").unwrap().fields.remove("user");
+        theirs.remove("bank");
+        theirs.insert("shop".into(), account(&[("pass", "c")]));
+        let base_path = write("base", &base);
+        let theirs_path = write("theirs", &theirs);
+
+        let mut merge = MergeArgs {
+            vault: theirs_path,
+            ..Default::default()
+        };
+        let mut app = App::test(Vault::create(dir.path().join("passwd"), "pw"));
+
+        // without a base nothing is removed and every edit conflicts
+        *app.vault.accounts_mut() = ours.clone();
+        merge.force = true;
+        handle_merge(&mut app, &merge).unwrap();
+        let mut names: Vec<&String> = app.vault.accounts().keys().collect();
+        names.sort();
+        assert_eq!(names, ["bank", "mail", "shop"]);
+        let mail = &app.vault.accounts()["mail"].fields;
+        assert_eq!(mail["pass"].value, "a2");
+        assert!(mail.contains_key("user"));
+
+        // with one, changes on either side are applied
+        *app.vault.accounts_mut() = ours;
+        merge.base = Some(base_path);
+        handle_merge(&mut app, &merge).unwrap();
+        assert_eq!(
+            app.vault.accounts(),
+            &HashMap::from([
+                (String::from("mail"), account(&[("pass", "a2")])),
+                (String::from("shop"), account(&[("pass", "c")])),
+            ])
+        );
+
+        // a vault that can't be opened is named in the error
+        merge.vault = dir.path().join("missing");
+        let err = handle_merge(&mut app, &merge).unwrap_err();
+        assert!(err.to_string().contains("missing"), "{}", err);
+    }
+}
@@ -0,0 +1,42 @@
+use anyhow::Result;
+use log::warn;
+use std::{
+    env,
+    io::{self, IsTerminal, Write},
+    process::{Command, Stdio},
+};
+
+/// Prints text through $PAGER, or less if it's unset, when paging was asked
+/// for and stdout is a terminal. Otherwise the text is printed as is
+pub fn page(text: &str, paging: bool) -> Result<()> {
+    if !paging || !io::stdout().is_terminal() {
+        print!("{}", text);
+        return Ok(());
+    }
+
+    let pager = env::var("PAGER")
+        .ok()
+        .filter(|p| !p.trim().is_empty())
+        .unwrap_or_else(|| String::from("less -R"));
+    let mut parts = pager.split_whitespace();
+    let program = parts.next().unwrap_or_default();
+    let mut child = match Command::new(program)
+        .args(parts)
+        .stdin(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            warn!("Failed to start pager {}: {}", program, e);
+            print!("{}", text);
+            return Ok(());
+        }
+    };
+
+    // quitting the pager before the end closes the pipe, which is fine
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    child.wait()?;
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use passcli::Result;
+
+use crate::{field::Field, Accounts, Error};
+
+/// Looks up a secret reference of the form account/field, the default field
+/// if only an account is given. Account names may contain slashes, so a name
+/// is only split at its last slash when it isn't an account itself
+pub fn lookup<'a>(
+    accounts: &'a Accounts,
+    reference: &str,
+    default_field: &str,
+) -> Result<&'a Field> {
+    let (account, field) = match reference.rsplit_once('/') {
+        Some(split) if !accounts.contains_key(reference) => split,
+        _ => (reference, default_field),
+    };
+    accounts
+        .get(account)
+        .ok_or_else(|| Error::AccountNotFound(account.to_string()))?
+        .fields
+        .get(field)
+        .ok_or_else(|| Error::FieldNotFound {
+            account: account.to_string(),
+            field: field.to_string(),
+        })
+}
+
+#[cfg(test)]
+mod reference_tests {
+    use std::collections::HashMap;
+
+    use crate::field::migrate;
+
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let accounts = migrate(HashMap::from([
+            (
+                String::from("db"),
+                HashMap::from([
+                    (String::from("pass"), String::from("a")),
+                    (String::from("user"), String::from("b")),
+                ]),
+            ),
+            (
+                String::from("work/db"),
+                HashMap::from([(String::from("pass"), String::from("c"))]),
+            ),
+        ]));
+        let value = |reference| lookup(&accounts, reference, "pass").map(|f| f.value.as_str());
+
+        assert_eq!(value("db").unwrap(), "a");
+        assert_eq!(value("db/user").unwrap(), "b");
+        assert_eq!(value("work/db").unwrap(), "c");
+        assert_eq!(value("work/db/pass").unwrap(), "c");
+        assert!(value("db/missing").is_err());
+        assert!(value("missing/pass").is_err());
+    }
+}
@@ -0,0 +1,134 @@
+use anyhow::{anyhow, Context, Result};
+use log::debug;
+use std::{
+    fs,
+    process::{Command, ExitStatus},
+};
+
+use crate::{config::RunArgs, reference::lookup, App, Error};
+
+/// Runs the command given after `--` with secrets added to its environment,
+/// mapped from `--env NAME=account/field` and the lines of `--env-file`. The
+/// vault is decrypted once and the command's exit status becomes passcli's
+pub fn handle_run(app: &mut App, args: &RunArgs) -> Result<()> {
+    let (program, program_args) = args
+        .command
+        .split_first()
+        .ok_or_else(|| Error::Usage(String::from("No command given, pass it after --")))?;
+
+    let mut mappings = Vec::new();
+    if let Some(path) = &args.env_file {
+        for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let mapping = parse_mapping(line)
+                .map_err(|e| anyhow!("{} line {}: {}", path.display(), number + 1, e))?;
+            mappings.extend(mapping);
+        }
+    }
+    for mapping in &args.env {
+        mappings.extend(parse_mapping(mapping)?);
+    }
+
+    let mut command = Command::new(program);
+    command.args(program_args);
+    for (name, reference) in mappings {
+        let field = lookup(app.vault.accounts(), &reference, &app.config.default_field)?;
+        command.env(name, &field.value);
+    }
+
+    let status = command
+        .status()
+        .with_context(|| format!("Failed to run {}", program))?;
+    debug!("{} exited with {}", program, status);
+    app.status = exit_code(status);
+    Ok(())
+}
+
+/// Parses a NAME=account/field mapping in the style of a .env file, blank
+/// lines and comments giving None
+fn parse_mapping(line: &str) -> Result<Option<(String, String)>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let line = line.strip_prefix("export ").unwrap_or(line);
+    let (name, reference) = line
+        .split_once('=')
+        .ok_or_else(|| Error::Usage(format!("Expected NAME=account/field, found {}", line)))?;
+
+    let name = name.trim();
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(Error::Usage(format!("Invalid variable name {}", name)).into());
+    }
+    let reference = reference.trim();
+    let reference = ['"', '\'']
+        .iter()
+        .find_map(|q| reference.strip_prefix(*q)?.strip_suffix(*q))
+        .unwrap_or(reference);
+    Ok(Some((name.to_string(), reference.to_string())))
+}
+
+/// the child's exit code, or 128 plus the signal that killed it
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
+
+#[cfg(test)]
+mod run_tests {
+    use crate::{
+        field::{Field, FieldKind},
+        Account, Vault,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_parse_mapping() {
+        let mapping = |line| parse_mapping(line).unwrap();
+        assert_eq!(
+            mapping("DB_PASS=db/pass"),
+            Some((String::from("DB_PASS"), String::from("db/pass")))
+        );
+        assert_eq!(
+            mapping("  export API_KEY = \"stripe/key\" "),
+            Some((String::from("API_KEY"), String::from("stripe/key")))
+        );
+        assert_eq!(mapping("# comment"), None);
+        assert_eq!(mapping(""), None);
+        assert!(parse_mapping("DB_PASS").is_err());
+        assert!(parse_mapping("DB PASS=db").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_handle_run() {
+        let mut app = App::test(Vault::create("", ""));
+        let mut account = Account::default();
+        account.fields.insert(
+            String::from("pass"),
+            Field::new(FieldKind::Password, String::from("secret")).unwrap(),
+        );
+        app.vault.accounts_mut().insert(String::from("db"), account);
+
+        // the child sees the mapped value and its status becomes passcli's
+        let mut args = RunArgs {
+            env: vec![String::from("X=db/pass")],
+            command: ["sh", "-c", "test \"$X\" = secret && exit 3"]
+                .map(String::from)
+                .to_vec(),
+            ..Default::default()
+        };
+        handle_run(&mut app, &args).unwrap();
+        assert_eq!(app.status, 3);
+
+        args.env = vec![String::from("X=db/missing")];
+        assert!(handle_run(&mut app, &args).is_err());
+        assert!(handle_run(&mut app, &RunArgs::default()).is_err());
+    }
+}
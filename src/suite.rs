@@ -0,0 +1,379 @@
+//! Cipher suites a vault can be encrypted with.
+//!
+//! A suite pairs the Argon2 variant turning the master password into the
+//! vault key with the AEAD cipher sealing the payload. The suite and its key
+//! derivation parameters are recorded in the header of the vault file, so
+//! files written with any suite stay readable when the default changes. Every
+//! suite is checked against published known-answer vectors below.
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm,
+};
+use argon2::{Algorithm, Argon2, Params, Version};
+use orion::hazardous::aead::xchacha20poly1305 as xchacha;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+use zeroize::Zeroizing;
+
+use crate::{Error, Result};
+
+/// length of the key of every suite
+const KEY_SIZE: usize = 32;
+/// length of the authentication tag of every suite
+const TAG_SIZE: usize = 16;
+/// Most memory in KiB a vault can ask for, 1 GiB. It's also the most
+/// kdf-bench recommends, so no vault written here needs more
+pub const MAX_MEMORY: u32 = 1 << 20;
+/// most passes a vault can ask for
+const MAX_ITERATIONS: u32 = 1 << 10;
+/// most lanes a vault can ask for
+const MAX_PARALLELISM: u32 = 1 << 6;
+
+/// Key a suite seals with, wiped when dropped
+pub type Key = xchacha::SecretKey;
+
+/// Argon2 parameters the vault key is derived with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    /// passes over the memory, 3 to 1024
+    pub iterations: u32,
+    /// memory in KiB, at least 8 per lane and at most 1 GiB
+    pub memory: u32,
+    /// lanes the memory is split into, computed one after another by this
+    /// build, at most 64
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            iterations: 3,
+            memory: 1 << 16,
+            parallelism: 1,
+        }
+    }
+}
+
+impl KdfParams {
+    /// Fails if the parameters can't derive a key, or would take more
+    /// memory or time than any vault needs. Headers are read before anything
+    /// is authenticated, so a tampered one can still make an unlock take up
+    /// to MAX_MEMORY and 1024 passes, but no more
+    pub fn validate(&self) -> Result<()> {
+        if self.iterations < 3 {
+            return Err(Error::KdfParams(String::from("at least 3 iterations")));
+        }
+        let limits = [
+            (self.iterations, MAX_ITERATIONS, "iterations"),
+            (self.memory, MAX_MEMORY, "KiB of memory"),
+            (self.parallelism, MAX_PARALLELISM, "lanes"),
+        ];
+        for (value, max, name) in limits {
+            if value > max {
+                return Err(Error::KdfParams(format!("at most {} {}", max, name)));
+            }
+        }
+        self.argon2(Algorithm::default()).map(|_| ())
+    }
+
+    fn argon2(&self, algorithm: Algorithm) -> Result<Argon2<'static>> {
+        let params = Params::new(
+            self.memory,
+            self.iterations,
+            self.parallelism,
+            Some(KEY_SIZE),
+        )
+        .map_err(|e| Error::KdfParams(e.to_string()))?;
+        Ok(Argon2::new(algorithm, Version::V0x13, params))
+    }
+}
+
+/// Key derivation function and cipher of a vault file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum Suite {
+    /// what every vault was written with before suites were recorded
+    #[default]
+    Argon2iXChaCha20Poly1305,
+    Argon2idXChaCha20Poly1305,
+    Argon2idAes256Gcm,
+}
+
+impl Suite {
+    pub const ALL: [Suite; 3] = [
+        Suite::Argon2iXChaCha20Poly1305,
+        Suite::Argon2idXChaCha20Poly1305,
+        Suite::Argon2idAes256Gcm,
+    ];
+
+    /// Name of the suite in headers and the config
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Argon2iXChaCha20Poly1305 => "argon2i-xchacha20poly1305",
+            Self::Argon2idXChaCha20Poly1305 => "argon2id-xchacha20poly1305",
+            Self::Argon2idAes256Gcm => "argon2id-aes256gcm",
+        }
+    }
+
+    /// Derives the key from the master password and the salt of the file
+    pub fn derive_key(&self, password: &str, salt: &[u8], params: &KdfParams) -> Result<Key> {
+        params.validate()?;
+        self.hash(password.as_bytes(), salt, params)
+    }
+
+    /// Seals plaintext under a random nonce, which is put in front, binding
+    /// the associated data to it
+    pub fn seal(&self, key: &Key, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let mut sealed = vec![0; self.nonce_size()];
+        rand::thread_rng().fill_bytes(&mut sealed);
+        let ciphertext = self.seal_with(key, &sealed, plaintext, aad)?;
+        sealed.extend(ciphertext);
+        Ok(sealed)
+    }
+
+    /// Opens what [`Suite::seal`] sealed, any mismatch of the key, the
+    /// ciphertext or the associated data failing as an incorrect password
+    pub fn open(&self, key: &Key, sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < self.nonce_size() + TAG_SIZE {
+            return Err(Error::IncorrectPassword);
+        }
+        let (nonce, ciphertext) = sealed.split_at(self.nonce_size());
+        self.open_with(key, nonce, ciphertext, aad)
+    }
+
+    fn algorithm(&self) -> Algorithm {
+        match self {
+            Self::Argon2iXChaCha20Poly1305 => Algorithm::Argon2i,
+            Self::Argon2idXChaCha20Poly1305 | Self::Argon2idAes256Gcm => Algorithm::Argon2id,
+        }
+    }
+
+    /// Bytes a sealed message has on top of its plaintext, the nonce in
+    /// front and the tag
+    pub fn overhead(&self) -> usize {
+        self.nonce_size() + TAG_SIZE
+    }
+
+    fn nonce_size(&self) -> usize {
+        match self {
+            Self::Argon2iXChaCha20Poly1305 | Self::Argon2idXChaCha20Poly1305 => 24,
+            Self::Argon2idAes256Gcm => 12,
+        }
+    }
+
+    /// Argon2 output without the checks of derive_key, so that known answers
+    /// with fewer passes can be tested
+    fn hash(&self, password: &[u8], salt: &[u8], params: &KdfParams) -> Result<Key> {
+        let mut key = Zeroizing::new([0; KEY_SIZE]);
+        params
+            .argon2(self.algorithm())?
+            .hash_password_into(password, salt, key.as_mut())
+            .map_err(|e| Error::KdfParams(e.to_string()))?;
+        Ok(Key::from_slice(key.as_ref())?)
+    }
+
+    /// ciphertext and tag of the plaintext under the given nonce
+    fn seal_with(&self, key: &Key, nonce: &[u8], plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::Argon2iXChaCha20Poly1305 | Self::Argon2idXChaCha20Poly1305 => {
+                let mut out = vec![0; plaintext.len() + TAG_SIZE];
+                let nonce = xchacha::Nonce::from_slice(nonce)?;
+                xchacha::seal(key, &nonce, plaintext, Some(aad), &mut out)?;
+                Ok(out)
+            }
+            Self::Argon2idAes256Gcm => Aes256Gcm::new_from_slice(key.unprotected_as_bytes())
+                .map_err(|_| Error::Crypto)?
+                .encrypt(
+                    nonce.into(),
+                    Payload {
+                        msg: plaintext,
+                        aad,
+                    },
+                )
+                .map_err(|_| Error::Crypto),
+        }
+    }
+
+    fn open_with(&self, key: &Key, nonce: &[u8], ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::Argon2iXChaCha20Poly1305 | Self::Argon2idXChaCha20Poly1305 => {
+                let mut out = vec![0; ciphertext.len() - TAG_SIZE];
+                let nonce = xchacha::Nonce::from_slice(nonce)?;
+                xchacha::open(key, &nonce, ciphertext, Some(aad), &mut out)
+                    .map_err(|_| Error::IncorrectPassword)?;
+                Ok(out)
+            }
+            Self::Argon2idAes256Gcm => Aes256Gcm::new_from_slice(key.unprotected_as_bytes())
+                .map_err(|_| Error::Crypto)?
+                .decrypt(
+                    nonce.into(),
+                    Payload {
+                        msg: ciphertext,
+                        aad,
+                    },
+                )
+                .map_err(|_| Error::IncorrectPassword),
+        }
+    }
+}
+
+impl fmt::Display for Suite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Suite {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|suite| suite.name() == s)
+            .ok_or_else(|| Error::UnsupportedSuite(s.to_string()))
+    }
+}
+
+impl From<Suite> for String {
+    fn from(suite: Suite) -> Self {
+        suite.name().to_string()
+    }
+}
+
+impl TryFrom<String> for Suite {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+#[cfg(test)]
+mod suite_tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// checks a cipher against a vector and that a flipped bit fails to open
+    fn check_cipher(suite: Suite, key: &str, nonce: &str, aad: &[u8], plain: &[u8], sealed: &str) {
+        let key = Key::from_slice(&hex(key)).unwrap();
+        let (nonce, mut sealed) = (hex(nonce), hex(sealed));
+        assert_eq!(suite.seal_with(&key, &nonce, plain, aad).unwrap(), sealed);
+        assert_eq!(suite.open_with(&key, &nonce, &sealed, aad).unwrap(), plain);
+        sealed[0] ^= 1;
+        assert!(suite.open_with(&key, &nonce, &sealed, aad).is_err());
+    }
+
+    #[test]
+    fn test_known_answers() {
+        // Argon2 reference implementation, t=2 m=2^16 p=1 "password" "somesalt"
+        let params = KdfParams {
+            iterations: 2,
+            ..Default::default()
+        };
+        for (suite, expected) in [
+            (
+                Suite::Argon2iXChaCha20Poly1305,
+                "c1628832147d9720c5bd1cfd61367078729f6dfb6f8fea9ff98158e0d7816ed0",
+            ),
+            (
+                Suite::Argon2idXChaCha20Poly1305,
+                "09316115d5cf24ed5a15a31a3ba326e5cf32edc24702987c02b6566f61913cf7",
+            ),
+            (
+                Suite::Argon2idAes256Gcm,
+                "09316115d5cf24ed5a15a31a3ba326e5cf32edc24702987c02b6566f61913cf7",
+            ),
+        ] {
+            let key = suite.hash(b"password", b"somesalt", &params).unwrap();
+            assert_eq!(key.unprotected_as_bytes(), hex(expected), "{}", suite);
+        }
+
+        // draft-irtf-cfrg-xchacha-03 A.3.1
+        let plain = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip \
+                      for the future, sunscreen would be it.";
+        let sealed = "bd6d179d3e83d43b9576579493c0e939572a1700252bfaccbed2902c21396cbb\
+                      731c7f1b0b4aa6440bf3a82f4eda7e39ae64c6708c54c216cb96b72e1213b452\
+                      2f8c9ba40db5d945b11b69b982c1bb9e3f3fac2bc369488f76b2383565d3fff9\
+                      21f9664c97637da9768812f615c68b13b52ec0875924c1c7987947deafd8780acf49";
+        for suite in [
+            Suite::Argon2iXChaCha20Poly1305,
+            Suite::Argon2idXChaCha20Poly1305,
+        ] {
+            check_cipher(
+                suite,
+                "808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f",
+                "404142434445464748494a4b4c4d4e4f5051525354555657",
+                &hex("50515253c0c1c2c3c4c5c6c7"),
+                plain,
+                sealed,
+            );
+        }
+
+        // NIST CAVP gcmEncryptExtIV256
+        check_cipher(
+            Suite::Argon2idAes256Gcm,
+            "92e11dcdaa866f5ce790fd24501f92509aacf4cb8b1339d50c9c1240935dd08b",
+            "ac93a1a6145299bde902f21a",
+            &hex("1e0889016f67601c8ebea4943bc23ad6"),
+            &hex("2d71bcfa914e4ac045b2aa60955fad24"),
+            "8995ae2e6df3dbf96fac7b7137bae67feca5aa77d51d4a0a14d9c51e1da474ab",
+        );
+    }
+
+    #[test]
+    fn test_derive_key_matches_earlier_files() {
+        // vaults were written with orion's Argon2i before suites
+        use orion::kdf;
+        let salt = kdf::Salt::from_slice(&[7; 16]).unwrap();
+        let password = kdf::Password::from_slice(b"kdf test password").unwrap();
+        let expected = kdf::derive_key(&password, &salt, 3, 1 << 16, KEY_SIZE as u32).unwrap();
+        let suite = Suite::default();
+        let params = KdfParams::default();
+        let key = suite
+            .derive_key("kdf test password", salt.as_ref(), &params)
+            .unwrap();
+        assert_eq!(key.unprotected_as_bytes(), expected.unprotected_as_bytes());
+
+        // and sealed with orion's aead, a nonce in front and no associated data
+        let sealed = orion::aead::seal(&expected, b"earlier vault").unwrap();
+        assert_eq!(suite.open(&key, &sealed, b"").unwrap(), b"earlier vault");
+
+        let weak = KdfParams {
+            iterations: 1,
+            ..Default::default()
+        };
+        assert!(matches!(weak.validate(), Err(Error::KdfParams(_))));
+        for huge in [
+            KdfParams {
+                memory: u32::MAX,
+                ..Default::default()
+            },
+            KdfParams {
+                memory: MAX_MEMORY * 2,
+                ..Default::default()
+            },
+            KdfParams {
+                iterations: u32::MAX,
+                ..Default::default()
+            },
+            KdfParams {
+                memory: u32::MAX,
+                parallelism: 1 << 20,
+                ..Default::default()
+            },
+        ] {
+            assert!(matches!(huge.validate(), Err(Error::KdfParams(_))));
+        }
+        assert!(matches!(
+            "rot13".parse::<Suite>(),
+            Err(Error::UnsupportedSuite(_))
+        ));
+    }
+}
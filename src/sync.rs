@@ -0,0 +1,262 @@
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info};
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use crate::{
+    config::SyncArgs,
+    crypt::{decrypt_vault, read_encrypted_file, Payload},
+    merge::merge_accounts,
+    vault::merge_states,
+    App,
+};
+
+/// Commits the vault file to the git repository containing it, initialising
+/// one if there is none. Messages never include account or field names
+pub fn commit(app: &App, message: &str) -> Result<()> {
+    let (dir, file) = split_path(app.vault.path())?;
+    if !git_ok(&dir, &["rev-parse", "--git-dir"])? {
+        git(&dir, &["init", "--quiet"])?;
+        info!("Initialised git repository in {}", dir.display());
+    }
+
+    if git(&dir, &["status", "--porcelain", "--", &file])?.is_empty() {
+        debug!("Vault unchanged, nothing to commit");
+        return Ok(());
+    }
+    git(&dir, &["add", "--", &file])?;
+    git(&dir, &["commit", "--quiet", "-m", message, "--", &file])?;
+    debug!("Vault committed");
+    Ok(())
+}
+
+/// Pulls the configured remote, merging diverged vaults at the account and
+/// field level, then pushes the result
+pub fn handle_sync(app: &mut App, args: &SyncArgs) -> Result<()> {
+    let (dir, file) = split_path(app.vault.path())?;
+    if !app.vault.path().exists() {
+        app.vault.save()?;
+    }
+    commit(app, "passcli: update vault")?;
+
+    let remote = app.config.sync_remote.clone();
+    let branch = match &app.config.sync_branch {
+        Some(b) => b.clone(),
+        None => git(&dir, &["rev-parse", "--abbrev-ref", "HEAD"])?,
+    };
+
+    if git_ok(
+        &dir,
+        &["ls-remote", "--exit-code", "--heads", &remote, &branch],
+    )? {
+        git(&dir, &["fetch", "--quiet", &remote, &branch])?;
+        pull(app, &dir, &file, &args.force)?;
+    } else {
+        info!("Branch {} not found on remote, pushing", branch);
+    }
+
+    git(
+        &dir,
+        &["push", "--quiet", &remote, &format!("HEAD:{}", branch)],
+    )?;
+    info!("Vault synchronised");
+    Ok(())
+}
+
+/// integrates FETCH_HEAD into the current branch
+fn pull(app: &mut App, dir: &Path, file: &str, force: &bool) -> Result<()> {
+    if git_ok(dir, &["merge-base", "--is-ancestor", "FETCH_HEAD", "HEAD"])? {
+        debug!("Remote has no new changes");
+        return Ok(());
+    }
+
+    if git_ok(dir, &["merge-base", "--is-ancestor", "HEAD", "FETCH_HEAD"])? {
+        git(dir, &["merge", "--quiet", "--ff-only", "FETCH_HEAD"])?;
+        let payload =
+            read_encrypted_file(app.vault.password(), app.vault.path(), &app.config.kdf())?;
+        *app.vault.accounts_mut() = payload.accounts;
+        merge_states(app.vault.audit_mut(), payload.audit);
+        info!("Fast-forwarded to remote changes");
+        return Ok(());
+    }
+
+    info!("Local and remote vaults diverged, merging");
+    let merge_base = git(dir, &["merge-base", "HEAD", "FETCH_HEAD"]).ok();
+    let base = match &merge_base {
+        Some(rev) => read_revision(app, dir, rev, file)?.unwrap_or_default(),
+        None => Payload::default(),
+    };
+    let theirs = read_revision(app, dir, "FETCH_HEAD", file)?.unwrap_or_default();
+    let merged = merge_accounts(
+        &base.accounts,
+        app.vault.accounts(),
+        &theirs.accounts,
+        force,
+    )?;
+
+    // record the merge with our tree, then replace it with the merged vault
+    let mut args = vec!["merge", "--quiet", "--no-ff", "--no-commit", "-s", "ours"];
+    if merge_base.is_none() {
+        args.push("--allow-unrelated-histories");
+    }
+    args.push("FETCH_HEAD");
+    git(dir, &args)?;
+
+    *app.vault.accounts_mut() = merged;
+    merge_states(app.vault.audit_mut(), theirs.audit);
+    app.vault.save()?;
+    git(dir, &["add", "--", file])?;
+    git(
+        dir,
+        &["commit", "--quiet", "-m", "passcli: merge remote vault"],
+    )?;
+    Ok(())
+}
+
+/// decrypts the vault as of a revision, None if it didn't exist then
+fn read_revision(app: &App, dir: &Path, rev: &str, file: &str) -> Result<Option<Payload>> {
+    let spec = format!("{}:./{}", rev, file);
+    if !git_ok(dir, &["cat-file", "-e", &spec])? {
+        return Ok(None);
+    }
+    let output = run(dir, &["show", &spec])?;
+    decrypt_vault(app.vault.password(), &output.stdout, &app.config.kdf())
+        .map(Some)
+        .with_context(|| {
+            format!(
+                "Failed to decrypt vault at {}, master passwords may differ",
+                rev
+            )
+        })
+}
+
+/// splits the vault path into its directory and file name
+fn split_path(path: &Path) -> Result<(PathBuf, String)> {
+    let file = path
+        .file_name()
+        .and_then(|f| f.to_str())
+        .ok_or_else(|| anyhow!("Invalid vault path"))?;
+    let dir = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    Ok((dir, file.to_string()))
+}
+
+fn run(dir: &Path, args: &[&str]) -> Result<Output> {
+    Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|e| anyhow!("Failed to run git: {}", e))
+}
+
+/// runs git and returns trimmed stdout, failing on a non-zero exit
+fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = run(dir, args)?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        Err(anyhow!(
+            "git {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// runs git and reports whether it exited successfully
+fn git_ok(dir: &Path, args: &[&str]) -> Result<bool> {
+    Ok(run(dir, args)?.status.success())
+}
+
+#[cfg(test)]
+mod sync_tests {
+    use std::{collections::HashMap, fs};
+
+    use crate::field::{Field, FieldKind};
+    use crate::Vault;
+
+    use super::*;
+
+    fn clone(remote: &Path, dir: &Path) -> App {
+        git(
+            dir.parent().unwrap(),
+            &[
+                "clone",
+                "--quiet",
+                remote.to_str().unwrap(),
+                dir.to_str().unwrap(),
+            ],
+        )
+        .unwrap();
+        git(dir, &["config", "user.name", "passcli"]).unwrap();
+        git(dir, &["config", "user.email", "passcli@localhost"]).unwrap();
+
+        let mut app = App::test(Vault::create(dir.join("passwd"), "sync test password"));
+        app.config.sync_branch = Some(String::from("main"));
+        app
+    }
+
+    fn account(pass: &str) -> crate::Account {
+        let field = Field {
+            kind: FieldKind::Password,
+            value: String::from(pass),
+        };
+        crate::Account::new(HashMap::from([(String::from("pass"), field)]))
+    }
+
+    #[test]
+    fn test_sync_merges_diverged_vaults() {
+        let root = tempfile::tempdir().unwrap();
+        let remote = root.path().join("remote.git");
+        fs::create_dir(&remote).unwrap();
+        git(
+            &remote,
+            &["init", "--quiet", "--bare", "--initial-branch=main"],
+        )
+        .unwrap();
+
+        let mut laptop = clone(&remote, &root.path().join("laptop"));
+        let mut desktop = clone(&remote, &root.path().join("desktop"));
+
+        laptop
+            .vault
+            .accounts_mut()
+            .insert(String::from("mail"), account("one"));
+        laptop.vault.save().unwrap();
+        commit(&laptop, "passcli: add").unwrap();
+        handle_sync(&mut laptop, &SyncArgs::default()).unwrap();
+
+        handle_sync(&mut desktop, &SyncArgs::default()).unwrap();
+        assert_eq!(desktop.vault.accounts(), laptop.vault.accounts());
+
+        // concurrent edits to different accounts
+        laptop
+            .vault
+            .accounts_mut()
+            .insert(String::from("bank"), account("two"));
+        laptop.vault.save().unwrap();
+        commit(&laptop, "passcli: add").unwrap();
+        desktop
+            .vault
+            .accounts_mut()
+            .insert(String::from("mail"), account("three"));
+        desktop.vault.save().unwrap();
+        commit(&desktop, "passcli: edit").unwrap();
+
+        handle_sync(&mut laptop, &SyncArgs::default()).unwrap();
+        handle_sync(&mut desktop, &SyncArgs::default()).unwrap();
+        handle_sync(&mut laptop, &SyncArgs::default()).unwrap();
+
+        let expected = HashMap::from([
+            (String::from("mail"), account("three")),
+            (String::from("bank"), account("two")),
+        ]);
+        assert_eq!(desktop.vault.accounts(), &expected);
+        assert_eq!(laptop.vault.accounts(), &expected);
+    }
+}
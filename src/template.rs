@@ -0,0 +1,217 @@
+//! Account templates kept as TOML files in `<config dir>/passcli/templates`.
+//!
+//! A template lists the fields of an account in the order they're asked for,
+//! each with a kind and either a default to offer or a generator policy:
+//!
+//! ```toml
+//! tags = ["database"]
+//! folder = "databases"
+//!
+//! [[field]]
+//! name = "host"
+//! kind = "text"
+//! default = "localhost"
+//!
+//! [[field]]
+//! name = "pass"
+//! generate = true
+//! length = 32
+//! disallow = "symbol"
+//! ```
+use anyhow::{anyhow, Result};
+use dialoguer::Input;
+use log::info;
+use serde::Deserialize;
+use std::{collections::BTreeSet, fs, path::PathBuf};
+
+use crate::{
+    account::{normalize_folder, Account},
+    config::{AddArgs, MetaArgs},
+    confirm,
+    field::{Field, FieldKind},
+    prompt_password, App, Error, CONFIRM_OVERWRITE_PROMPT,
+};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Template {
+    #[serde(default)]
+    tags: BTreeSet<String>,
+    folder: Option<String>,
+    #[serde(default, rename = "field")]
+    fields: Vec<TemplateField>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TemplateField {
+    name: String,
+    /// guessed from the name if omitted
+    kind: Option<FieldKind>,
+    /// shown instead of the field name when asking for the value
+    prompt: Option<String>,
+    /// offered when asking for the value
+    default: Option<String>,
+    #[serde(default)]
+    generate: bool,
+    /// generated length, the config's default_gen if omitted
+    length: Option<usize>,
+    /// characters the generator avoids, the config's default_disallow if
+    /// omitted
+    disallow: Option<String>,
+}
+
+impl Template {
+    /// Builds an account from the template, generating the fields it says to
+    /// and taking the rest from input
+    fn fill(
+        &self,
+        app: &App,
+        meta: &MetaArgs,
+        mut input: impl FnMut(&TemplateField, FieldKind) -> Result<String>,
+    ) -> Result<Account> {
+        let mut account = Account {
+            tags: self.tags.clone(),
+            folder: self.folder.as_deref().and_then(normalize_folder),
+            ..Default::default()
+        };
+        for field in &self.fields {
+            let kind = field.kind.unwrap_or_else(|| FieldKind::infer(&field.name));
+            let value = if field.generate {
+                kind.generate(
+                    field.length,
+                    app.config.default_gen,
+                    field
+                        .disallow
+                        .as_ref()
+                        .unwrap_or(&app.config.default_disallow),
+                )?
+            } else {
+                input(field, kind)?
+            };
+            let value =
+                Field::new(kind, value).map_err(|e| anyhow!("Field {}: {}", field.name, e))?;
+            if account.fields.insert(field.name.clone(), value).is_some() {
+                return Err(anyhow!(
+                    "Field {} appears twice in the template",
+                    field.name
+                ));
+            }
+        }
+        meta.apply(&mut account);
+        Ok(account)
+    }
+}
+
+/// directory user templates are read from
+pub fn templates_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|p| p.join("passcli/templates"))
+}
+
+fn load(name: &str) -> Result<Template> {
+    let dir = templates_dir().ok_or_else(|| anyhow!("Config directory not found"))?;
+    let path = dir.join(format!("{}.toml", name));
+    if !path.exists() {
+        return Err(anyhow!("Template {} not found in {}", name, dir.display()));
+    }
+    toml::from_str(&fs::read_to_string(&path)?)
+        .map_err(|e| anyhow!("Template {} is invalid: {}", name, e))
+}
+
+/// Adds an account with every field of a template in one go, prompting for
+/// the values that aren't generated
+pub fn handle_template_add(app: &mut App, args: &AddArgs, name: &str) -> Result<()> {
+    let account = &args.account;
+    if app.vault.accounts().contains_key(account)
+        && !confirm(CONFIRM_OVERWRITE_PROMPT, false, &args.force)?
+    {
+        return Err(Error::Aborted.into());
+    }
+
+    let template = load(name)?;
+    let force = args.force;
+    let filled = template.fill(app, &args.meta, |field, kind| {
+        let prompt = field.prompt.as_deref().unwrap_or(&field.name);
+        if kind.is_secret() {
+            return Ok(prompt_password(prompt, true, &force)?);
+        }
+        let mut input = Input::<String>::new().with_prompt(prompt);
+        if let Some(default) = &field.default {
+            input = input.default(default.clone());
+        }
+        Ok(input.interact_text()?)
+    })?;
+
+    if !args.value.hide {
+        for (field, value) in template
+            .fields
+            .iter()
+            .filter(|f| f.generate)
+            .filter_map(|f| Some((&f.name, filled.fields.get(&f.name)?)))
+        {
+            println!("{}: {}", field, value.value);
+        }
+    }
+    app.vault.accounts_mut().insert(account.clone(), filled);
+    info!("Account created from template {}", name);
+    Ok(())
+}
+
+#[cfg(test)]
+mod template_tests {
+    use crate::Vault;
+
+    use super::*;
+
+    #[test]
+    fn test_fill() {
+        let template: Template = toml::from_str(
+            r#"
+            tags = ["database"]
+            folder = "/databases/"
+
+            [[field]]
+            name = "host"
+            kind = "text"
+            default = "localhost"
+
+            [[field]]
+            name = "user"
+
+            [[field]]
+            name = "pass"
+            generate = true
+            length = 24
+            "#,
+        )
+        .unwrap();
+        let app = App::test(Vault::create("", ""));
+
+        let meta = MetaArgs {
+            tag: vec![String::from("prod")],
+            ..Default::default()
+        };
+        let mut asked = Vec::new();
+        let account = template
+            .fill(&app, &meta, |field, _| {
+                asked.push(field.name.clone());
+                Ok(field
+                    .default
+                    .clone()
+                    .unwrap_or_else(|| String::from("admin")))
+            })
+            .unwrap();
+
+        assert_eq!(asked, ["host", "user"]);
+        assert_eq!(account.fields["host"].value, "localhost");
+        assert_eq!(account.fields["user"].kind, FieldKind::Username);
+        assert_eq!(account.fields["pass"].value.len(), 24);
+        assert_eq!(account.folder.as_deref(), Some("databases"));
+        assert_eq!(
+            account.tags,
+            BTreeSet::from([String::from("database"), String::from("prod")])
+        );
+
+        assert!(toml::from_str::<Template>("[[field]]\nname = \"a\"\nlenght = 3\n").is_err());
+    }
+}
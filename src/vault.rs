@@ -0,0 +1,328 @@
+//! A vault file opened for reading and changing its accounts.
+//!
+//! Opening reads the file, unlocking decrypts it with the master password.
+//! Changes stay in memory until [`Vault::save`], which writes the whole vault
+//! again and keeps the previous file as a backup.
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    account::{stamp, Account},
+    compression::Compression,
+    crypt::{decrypt_vault, write_encrypted_file, Encoding},
+    field::Field,
+    suite::{KdfParams, Suite},
+    Accounts, Error, Result,
+};
+
+/// backups kept of vaults written with the default config
+const DEFAULT_BACKUP_COUNT: usize = 3;
+
+/// Audit logs known to a vault by id, several machines sharing a vault
+/// through sync or merge each keep their own
+pub type AuditStates = BTreeMap<String, AuditState>;
+
+/// The key of an audit log and its anchor as of the last vault write
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditState {
+    pub key: Vec<u8>,
+    /// number of entries as of the last vault write
+    pub seq: u64,
+    /// hash of the last of those entries
+    pub head: String,
+    /// user and host keeping the log, empty for logs created before it was
+    /// recorded
+    #[serde(default)]
+    pub origin: String,
+}
+
+/// Merges the audit log anchors of another copy of a vault, keeping the
+/// furthest anchor of each log
+pub fn merge_states(ours: &mut AuditStates, theirs: AuditStates) {
+    for (id, state) in theirs {
+        match ours.get(&id) {
+            Some(existing) if existing.seq >= state.seq => {}
+            _ => {
+                ours.insert(id, state);
+            }
+        }
+    }
+}
+
+/// A vault file that has been read but not decrypted
+#[derive(Debug)]
+pub struct LockedVault {
+    path: PathBuf,
+    data: Vec<u8>,
+    kdf: KdfParams,
+}
+
+impl LockedVault {
+    /// Sets the key derivation parameters of a file written before they
+    /// were recorded in its header
+    pub fn kdf(mut self, params: KdfParams) -> Self {
+        self.kdf = params;
+        self
+    }
+
+    /// Decrypts the vault, older payloads are migrated to the current one.
+    /// The vault keeps the suite, parameters and compression it was written
+    /// with, and stays armored if it was
+    pub fn unlock(self, password: &str) -> Result<Vault> {
+        let payload = decrypt_vault(password, &self.data, &self.kdf)?;
+        Ok(Vault {
+            path: self.path,
+            password: password.to_string(),
+            suite: payload.header.suite,
+            kdf: payload.header.kdf,
+            compression: payload.header.compression,
+            armor: payload.header.encoding == Encoding::Armored,
+            backup_count: DEFAULT_BACKUP_COUNT,
+            accounts: payload.accounts,
+            audit: payload.audit,
+        })
+    }
+}
+
+/// The decrypted accounts of a vault file with what's needed to write it back
+#[derive(Debug)]
+pub struct Vault {
+    pub(crate) path: PathBuf,
+    pub(crate) password: String,
+    pub(crate) suite: Suite,
+    pub(crate) kdf: KdfParams,
+    pub(crate) compression: Compression,
+    pub(crate) armor: bool,
+    pub(crate) backup_count: usize,
+    pub(crate) accounts: Accounts,
+    pub(crate) audit: AuditStates,
+}
+
+impl Vault {
+    /// Reads a vault file, see [`LockedVault::unlock`]
+    pub fn open(path: impl Into<PathBuf>) -> Result<LockedVault> {
+        let path = path.into();
+        let data = fs::read(&path)?;
+        Ok(LockedVault {
+            path,
+            data,
+            kdf: KdfParams::default(),
+        })
+    }
+
+    /// An empty vault, nothing is written until it's saved
+    pub fn create(path: impl Into<PathBuf>, password: &str) -> Self {
+        Self {
+            path: path.into(),
+            password: password.to_string(),
+            suite: Suite::default(),
+            kdf: KdfParams::default(),
+            compression: Compression::None,
+            armor: false,
+            backup_count: DEFAULT_BACKUP_COUNT,
+            accounts: Accounts::new(),
+            audit: AuditStates::new(),
+        }
+    }
+
+    /// Sets the key derivation parameters the vault is saved with
+    pub fn kdf(mut self, params: KdfParams) -> Self {
+        self.kdf = params;
+        self
+    }
+
+    /// Sets the cipher suite the vault is saved with
+    pub fn suite(mut self, suite: Suite) -> Self {
+        self.suite = suite;
+        self
+    }
+
+    /// Sets how the payload is compressed when saving
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets whether the vault is saved as ASCII armored text instead of binary
+    pub fn armor(mut self, armor: bool) -> Self {
+        self.armor = armor;
+        self
+    }
+
+    /// Sets how many previous files are kept next to the vault when saving
+    pub fn backup_count(mut self, count: usize) -> Self {
+        self.backup_count = count;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn password(&self) -> &str {
+        &self.password
+    }
+
+    /// Changes the master password the vault is saved with
+    pub fn set_password(&mut self, password: &str) {
+        self.password = password.to_string();
+    }
+
+    pub fn cipher_suite(&self) -> Suite {
+        self.suite
+    }
+
+    /// Changes the cipher suite the vault is saved with
+    pub fn set_suite(&mut self, suite: Suite) {
+        self.suite = suite;
+    }
+
+    pub fn kdf_params(&self) -> &KdfParams {
+        &self.kdf
+    }
+
+    /// Changes the key derivation parameters the vault is saved with
+    pub fn set_kdf(&mut self, params: KdfParams) {
+        self.kdf = params;
+    }
+
+    pub fn accounts(&self) -> &Accounts {
+        &self.accounts
+    }
+
+    /// Mutable access to every account, changes made this way aren't stamped
+    pub fn accounts_mut(&mut self) -> &mut Accounts {
+        &mut self.accounts
+    }
+
+    pub fn audit(&self) -> &AuditStates {
+        &self.audit
+    }
+
+    pub fn audit_mut(&mut self) -> &mut AuditStates {
+        &mut self.audit
+    }
+
+    pub fn account(&self, account: &str) -> Result<&Account> {
+        self.accounts
+            .get(account)
+            .ok_or_else(|| Error::AccountNotFound(account.to_string()))
+    }
+
+    /// Gets a field of an account
+    pub fn get(&self, account: &str, field: &str) -> Result<&Field> {
+        self.account(account)?
+            .fields
+            .get(field)
+            .ok_or_else(|| Error::FieldNotFound {
+                account: account.to_string(),
+                field: field.to_string(),
+            })
+    }
+
+    /// Sets a field, creating the account if needed, and returns the value
+    /// it replaced
+    pub fn set(&mut self, account: &str, field: &str, value: Field) -> Option<Field> {
+        self.change(|accounts| {
+            accounts
+                .entry(account.to_string())
+                .or_default()
+                .fields
+                .insert(field.to_string(), value)
+        })
+    }
+
+    /// Removes an account
+    pub fn remove(&mut self, account: &str) -> Result<Account> {
+        self.accounts
+            .remove(account)
+            .ok_or_else(|| Error::AccountNotFound(account.to_string()))
+    }
+
+    /// Removes a field of an account
+    pub fn remove_field(&mut self, account: &str, field: &str) -> Result<Field> {
+        self.account(account)?;
+        self.change(|accounts| accounts.get_mut(account)?.fields.remove(field))
+            .ok_or_else(|| Error::FieldNotFound {
+                account: account.to_string(),
+                field: field.to_string(),
+            })
+    }
+
+    /// Renames an account, failing if the new name is taken
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        if self.accounts.contains_key(to) {
+            return Err(Error::AccountExists(to.to_string()));
+        }
+        let account = self.remove(from)?;
+        self.accounts.insert(to.to_string(), account);
+        Ok(())
+    }
+
+    /// Encrypts the vault to its file, the previous file becoming a backup
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_encrypted_file(self)
+    }
+
+    /// applies a change to the accounts, stamping the accounts it changed
+    fn change<T>(&mut self, f: impl FnOnce(&mut Accounts) -> T) -> T {
+        let before = self.accounts.clone();
+        let result = f(&mut self.accounts);
+        stamp(&before, &mut self.accounts);
+        result
+    }
+}
+
+#[cfg(test)]
+mod vault_tests {
+    use crate::field::FieldKind;
+
+    use super::*;
+
+    #[test]
+    fn test_vault() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("passwd");
+        let mut vault = Vault::create(&path, "vault test password");
+        let pass = Field::new(FieldKind::Password, String::from("hunter2")).unwrap();
+        assert!(vault.set("mail", "pass", pass.clone()).is_none());
+        let user = Field::new(FieldKind::Username, String::from("me")).unwrap();
+        vault.set("mail", "user", user);
+        assert!(vault.account("mail").unwrap().modified.is_some());
+        vault.save().unwrap();
+
+        assert!(matches!(
+            Vault::open(&path).unwrap().unlock("wrong"),
+            Err(Error::IncorrectPassword)
+        ));
+        let mut vault = Vault::open(&path)
+            .unwrap()
+            .unlock("vault test password")
+            .unwrap();
+        assert_eq!(vault.get("mail", "pass").unwrap(), &pass);
+        vault.rename("mail", "email").unwrap();
+        vault.set("bank", "pass", pass.clone());
+        assert!(matches!(
+            vault.rename("bank", "email"),
+            Err(Error::AccountExists(_))
+        ));
+        assert_eq!(vault.remove_field("email", "user").unwrap().value, "me");
+        assert!(matches!(
+            vault.get("email", "user"),
+            Err(Error::FieldNotFound { .. })
+        ));
+        vault.remove("bank").unwrap();
+        assert!(matches!(
+            vault.remove("bank"),
+            Err(Error::AccountNotFound(_))
+        ));
+        assert_eq!(vault.accounts().len(), 1);
+    }
+}